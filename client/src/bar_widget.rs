@@ -1,9 +1,11 @@
+use rsbar_protocol::EventKind;
+
 use crate::unix_sockets::ChannelsData;
 
 pub trait BarWidget {
     fn bind_widget  (&self, container: &gtk4::Box);
+    fn root_widget  (&self) -> gtk4::Widget;
     fn bind_channels(&self, channels_data: ChannelsData);
 
-    // TODO use just Vec<String>?
-    fn events_list(&self) -> &'static[&'static str];
+    fn events_list(&self) -> &'static[EventKind];
 }
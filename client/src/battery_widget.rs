@@ -1,6 +1,6 @@
 use crate::{bar_widget::BarWidget, unix_sockets::ChannelsData};
-use gtk4::{glib::{clone::Downgrade, MainContext}, prelude::{BoxExt, WidgetExt}};
-use log::warn;
+use gtk4::{glib::{clone::Downgrade, MainContext}, prelude::{BoxExt, Cast, WidgetExt}};
+use rsbar_protocol::{BatteryStatus, Event, EventKind};
 
 const BATTERY_ICONS: &[&str] = &[
     "󰂎", "󰁺", "󰁻", "󰁼", "󰁽", "󰁾", "󰁿", "󰂀", "󰂁", "󰂂", "󰁹"
@@ -10,9 +10,9 @@ const CHARGING_BATTERY_ICONS: &[&str] = &[
     "󰢟", "󰢜", "󰂆", "󰂇", "󰂈", "󰢝", "󰂉", "󰢞", "󰂊", "󰂋", "󰂅"
 ];
 
-const EVENTS_LIST: &[&str] = &[
-    "battery/capacity",
-    "battery/status",
+const EVENTS_LIST: &[EventKind] = &[
+    EventKind::BatteryCapacity,
+    EventKind::BatteryStatus,
 ];
 
 pub struct BatteryWidget {
@@ -35,43 +35,37 @@ impl BarWidget for BatteryWidget {
         container.append(&self.label);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
+    fn root_widget(&self) -> gtk4::Widget {
+        self.label.clone().upcast()
+    }
+
+    fn events_list(&self) -> &'static[EventKind] {
         EVENTS_LIST
     }
     
-    fn bind_channels(&self, mut channels_data: ChannelsData) {
+    fn bind_channels(&self, channels_data: ChannelsData) {
         let weak_label = self.label.downgrade();
+        let mut events = channels_data.event_bus.subscribe(EVENTS_LIST);
 
         MainContext::default().spawn_local(async move {
 
             let mut current_capacity = 0;
             let mut is_charging      = false;
 
-            while let Ok(event) = channels_data.event_rx.recv().await {
-                if event.name == EVENTS_LIST[0] {
-                    match event.value.parse::<u32>() {
-                        Ok(capacity) => current_capacity = capacity,
-                        Err(_) => {
-                            warn!("Invalid battery capacity value: {}", event.value);
-                        },
-                    }
-                }
-
-                if event.name == EVENTS_LIST[1] {
-                    match event.value.as_str() {
-                        "Charging" => is_charging = true,
-                        _          => is_charging = false,
-                    }
+            while let Some(event) = events.recv().await {
+                match event {
+                    Event::BatteryCapacity(capacity) => current_capacity = capacity.min(100),
+                    Event::BatteryStatus(status)     => is_charging = status == BatteryStatus::Charging,
+                    _ => continue,
                 }
 
                 let icon_number = (current_capacity / 10) as usize;
-                let icon: &str;
 
-                if is_charging {
-                    icon = CHARGING_BATTERY_ICONS[icon_number];
+                let icon = if is_charging {
+                    CHARGING_BATTERY_ICONS[icon_number]
                 } else {
-                    icon = BATTERY_ICONS[icon_number];
-                }
+                    BATTERY_ICONS[icon_number]
+                };
 
                 weak_label.upgrade().unwrap().set_text(icon);
             }
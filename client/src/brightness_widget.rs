@@ -1,3 +1,5 @@
+use rsbar_protocol::{Call, Event, EventKind};
+
 use crate::bar_widget::BarWidget;
 use crate::slider_widget::{SliderFetchResult, SliderWidget};
 use crate::unix_sockets::ChannelsData;
@@ -6,8 +8,8 @@ const MAX_BRIGHTNESS: f64 = 100.0;
 const SLIDER_HEIGHT:  i32 = 100;
 const BRIGHTNESS_ICON: [&str; 1] = ["󰖙"];
 
-const EVENTS_LIST: &[&str] = &[
-    "brightness/brightness",
+const EVENTS_LIST: &[EventKind] = &[
+    EventKind::Brightness,
 ];
 
 #[derive(Clone)]
@@ -20,6 +22,7 @@ impl BrightnessWidget {
         BrightnessWidget {
             slider_widget: SliderWidget::builder()
                 .icons(&BRIGHTNESS_ICON.map(|x| x.to_string()))
+                .events(EVENTS_LIST)
                 .transition_duration(duration)
                 .slider_height(SLIDER_HEIGHT)
                 .max_value(MAX_BRIGHTNESS)
@@ -39,7 +42,11 @@ impl BarWidget for BrightnessWidget {
         self.slider_widget.bind_widget(container);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
+    fn root_widget(&self) -> gtk4::Widget {
+        self.slider_widget.root_widget()
+    }
+
+    fn events_list(&self) -> &'static[EventKind] {
         EVENTS_LIST
     }
 
@@ -48,22 +55,14 @@ impl BarWidget for BrightnessWidget {
     }
 }
 
-fn set_system_brightness(brightness: f64) -> String {
-    format!("brightness/setBrightness/{}", (brightness * MAX_BRIGHTNESS) as u32)
+fn set_system_brightness(brightness: f64) -> Option<Call> {
+    Some(Call::SetBrightness((brightness * MAX_BRIGHTNESS) as u32))
 }
 
-fn get_system_brightness(name: &str, value: &str) -> SliderFetchResult {
-
-    if name != EVENTS_LIST[0] {
-        return SliderFetchResult::None;
+fn get_system_brightness(event: &Event) -> SliderFetchResult {
+    match event {
+        Event::Brightness(brightness) => SliderFetchResult::Value(*brightness as f64 / MAX_BRIGHTNESS),
+        _                             => SliderFetchResult::None,
     }
-
-    let value_float = value.parse::<f64>();
-
-    if value_float.is_err() {
-        return SliderFetchResult::None;
-    }
-
-    SliderFetchResult::Value(value_float.unwrap() / MAX_BRIGHTNESS)
 }
 
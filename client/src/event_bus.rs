@@ -0,0 +1,92 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+
+use log::warn;
+use rsbar_protocol::{Event, EventKind};
+use tokio::sync::{broadcast, mpsc};
+
+// Events queued for a single receiver. A receiver which falls behind is resynced from the last values
+const RECEIVER_CAPACITY: usize = 32;
+
+// Routes the daemon events to the widgets which subscribed to them.
+// Last value of each event is stored, so lagging receivers don't have to replay the missed events
+#[derive(Clone)]
+pub struct EventBus {
+    state:           Arc<Mutex<BusState>>,
+    subscription_tx: mpsc::UnboundedSender<EventKind>,
+}
+
+#[derive(Default)]
+struct BusState {
+    last_values: HashMap<EventKind, Event>,
+    routes:      HashMap<EventKind, Vec<broadcast::Sender<Event>>>,
+}
+
+pub struct EventReceiver {
+    events:   &'static [EventKind],
+    receiver: broadcast::Receiver<Event>,
+    state:    Arc<Mutex<BusState>>,
+    pending:  VecDeque<Event>,
+}
+
+impl EventBus {
+    // Events subscribed on the bus are requested from the daemon through the subscription channel
+    pub fn new(subscription_tx: mpsc::UnboundedSender<EventKind>) -> Self {
+        EventBus {
+            state: Arc::new(Mutex::new(BusState::default())),
+            subscription_tx,
+        }
+    }
+
+    pub fn subscribe(&self, events: &'static [EventKind]) -> EventReceiver {
+        let (sender, receiver) = broadcast::channel(RECEIVER_CAPACITY);
+        let mut state = self.state.lock().unwrap();
+
+        for event in events {
+            state.routes.entry(*event).or_default().push(sender.clone());
+
+            let _ = self.subscription_tx.send(*event);
+        }
+
+        // NOTE values received before subscription are delivered right away
+        let pending = events.iter().filter_map(|event| state.last_values.get(event).cloned()).collect();
+
+        EventReceiver { events, receiver, state: self.state.clone(), pending }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let kind      = event.kind();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(senders) = state.routes.get_mut(&kind) {
+            // Receivers of the dropped widgets are removed here
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+        }
+
+        state.last_values.insert(kind, event);
+    }
+}
+
+impl EventReceiver {
+    // Returns None once the bus is dropped
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+
+            match self.receiver.recv().await {
+                Ok(event)                                       => return Some(event),
+                Err(broadcast::error::RecvError::Closed)        => return None,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Widget missed {count} events, resyncing from the last values");
+
+                    // NOTE queued events are older than the last values, so they are skipped
+                    self.receiver = self.receiver.resubscribe();
+
+                    let state = self.state.lock().unwrap();
+                    self.pending = self.events.iter().filter_map(|event| state.last_values.get(event).cloned()).collect();
+                },
+            }
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use std::{cell::RefCell, rc::Rc, usize};
+use std::{cell::RefCell, rc::Rc};
 
-use gtk4::{glib::MainContext, prelude::{BoxExt, GestureExt, GridExt, WidgetExt}};
+use gtk4::{glib::MainContext, prelude::{BoxExt, Cast, GestureExt, GridExt, WidgetExt}};
+use rsbar_protocol::{Call, Event, EventKind};
 
 use crate::{bar_widget::BarWidget, unix_sockets::ChannelsData};
 
-const EVENTS_LIST: &[&str] = &[
-    "hyprland/workspace",
+const EVENTS_LIST: &[EventKind] = &[
+    EventKind::HyprlandWorkspace,
 ];
 
 //--------------------------------------------------------------------------------------------------------------------------------
@@ -23,11 +24,15 @@ impl BarWidget for HyprlandWorkspacesWidget {
         container.append(&self.container);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
+    fn root_widget(&self) -> gtk4::Widget {
+        self.container.clone().upcast()
+    }
+
+    fn events_list(&self) -> &'static[EventKind] {
         EVENTS_LIST
     }
 
-    fn bind_channels(&self, mut channels_data: ChannelsData) {
+    fn bind_channels(&self, channels_data: ChannelsData) {
         for button_index in 0..self.buttons.len() {
             let gesture = gtk4::GestureClick::new();
 
@@ -36,7 +41,7 @@ impl BarWidget for HyprlandWorkspacesWidget {
             gesture.connect_released(move |gesture, _, _, _| {
                 gesture.set_state(gtk4::EventSequenceState::Claimed);
     
-                let _ = call_tx.send(format!("hyprland/setWorkspace/{}", button_index + 1));
+                call_tx.send(Call::SetWorkspace(button_index as i32 + 1));
             });
     
             self.buttons[button_index].add_controller(gesture);
@@ -44,22 +49,23 @@ impl BarWidget for HyprlandWorkspacesWidget {
 
         let buttons        = self.buttons.clone();
         let last_workspace = self.last_workspace.clone();
+        let mut events     = channels_data.event_bus.subscribe(EVENTS_LIST);
         
 
         MainContext::default().spawn_local(async move {
-            while let Ok(event) = channels_data.event_rx.recv().await {
-                if event.name != EVENTS_LIST[0] {
+            while let Some(event) = events.recv().await {
+                let Event::HyprlandWorkspace(workspace) = event else {
                     continue;
-                }
+                };
 
-                let workspace_id = get_workspace_id(&event.value, buttons.len());
+                let workspace_id = get_workspace_id(workspace, buttons.len());
                 buttons[*last_workspace.borrow() - 1].remove_css_class("hyprland-workspaces-widget-picked");
 
                 if workspace_id.is_none() {
                     continue;
                 }
 
-                buttons[(workspace_id.unwrap() - 1) as usize].add_css_class("hyprland-workspaces-widget-picked");
+                buttons[workspace_id.unwrap() - 1].add_css_class("hyprland-workspaces-widget-picked");
 
                 *last_workspace.borrow_mut() = workspace_id.unwrap();
             }
@@ -67,20 +73,12 @@ impl BarWidget for HyprlandWorkspacesWidget {
     }
 }
 
-fn get_workspace_id(value: &str, max_id: usize) -> Option<usize> {
-    let workspace_id = value.parse::<i32>();
-
-    if workspace_id.is_err() {
-        return None;
-    }
-
-    let workspace_id_unwrapped = workspace_id.unwrap();
-
-    if workspace_id_unwrapped < 1 || workspace_id_unwrapped as usize > max_id {
+fn get_workspace_id(workspace_id: i32, max_id: usize) -> Option<usize> {
+    if workspace_id < 1 || workspace_id as usize > max_id {
         return None;
     }
 
-    Some(workspace_id_unwrapped as usize)
+    Some(workspace_id as usize)
 }
 
 impl HyprlandWorkspacesWidget {
@@ -94,13 +92,11 @@ impl HyprlandWorkspacesWidget {
             }
         }
 
-        let widget = Self { 
+        Self { 
             container,
             buttons:        Rc::new(buttons),
             last_workspace: Rc::new(RefCell::new(1)) 
-        };
-
-        widget
+        }
     }
 
     fn create_button(container: &gtk4::Grid, buttons: &mut Vec<gtk4::Label>, row: usize, col: usize, cols_count: usize) {
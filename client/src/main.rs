@@ -6,9 +6,10 @@ mod brightness_widget;
 mod hyprland_workspaces_widget;
 mod tokio_runtime;
 mod unix_sockets;
+mod event_bus;
 mod battery_widget;
 
-use std::{fs, path::Path, process::exit};
+use std::{collections::HashSet, fs, path::Path, process::exit};
 
 use bar_widget::BarWidget;
 use battery_widget::BatteryWidget;
@@ -18,18 +19,31 @@ use tokio_runtime::tokio_runtime;
 use unix_sockets::{setup_unix_sockets, ChannelsData};
 use volume_widget::VolumeWidget;
 use hyprland_workspaces_widget::HyprlandWorkspacesWidget;
-use gtk4::{prelude::*, Application, ApplicationWindow};
+use gtk4::{glib::MainContext, prelude::*, Application, ApplicationWindow};
+use rsbar_protocol::{Event, EventKind, SocketPaths};
 use gtk4_layer_shell::{Edge, LayerShell, Layer};
 use time_widget::TimeWidget;
 
 static CONFIG_PATH: &str = ".config/rsbar/style.css";
+static DISCONNECTED_CLASS: &str = "widget-disconnected";
+static CONTEXT_STATUS_EVENTS: &[EventKind] = &[EventKind::ContextStatus];
 
 fn main() {
     colog::init();
 
-    let channels_data = tokio_runtime().block_on(setup_unix_sockets()).unwrap();
+    // Socket flags are consumed here, the rest of the arguments is passed to gtk
+    let mut args: Vec<String> = std::env::args().collect();
+    let paths = match SocketPaths::from_args(&mut args) {
+        Ok(paths)  => paths,
+        Err(error) => {
+            error!("Unable to resolve the daemon sockets: {error}");
+            exit(1);
+        },
+    };
 
-    let app_id = format!("org.rsbar.bar");
+    let channels_data = setup_unix_sockets(&paths);
+
+    let app_id = "org.rsbar.bar".to_string();
     let app    = Application::builder().application_id(app_id).build();
 
     app.connect_startup(move |app| {
@@ -47,7 +61,7 @@ fn main() {
         tokio_runtime().block_on(build_ui(app, &channels_data));
     });
     
-    app.run();
+    app.run_with_args(&args);
 }
 
 fn read_css_config() -> String {
@@ -131,17 +145,55 @@ async fn build_window(app: &Application, display: &gtk4::gdk::Display, monitor_i
     
     let widgets: Vec<Box<dyn BarWidget>> = vec![time, battery, workspaces, volume, brightness];
 
+    // NOTE widgets subscribe to their events on the event bus, which requests them from the daemon
     for widget in widgets {
-        let events = widget.events_list();
-
-        for event in events {
-            let _ = channels_data.event_subscription_tx.send(event.to_string()).await;
-        }
-
         widget.bind_channels(channels_data.clone());
+
+        bind_widget_state(widget.as_ref(), channels_data.clone());
     }
 }
 
+// Widget is greyed out while the daemon is disconnected or any of its contexts is not running in the daemon.
+// Values of a disconnected widget are stale, so it also gets the "widget-disconnected" class
+fn bind_widget_state(widget: &dyn BarWidget, mut channels_data: ChannelsData) {
+    let contexts: HashSet<_> = widget.events_list().iter().map(|event| event.context()).collect();
+    let weak_root  = widget.root_widget().downgrade();
+    let mut events = channels_data.event_bus.subscribe(CONTEXT_STATUS_EVENTS);
+
+    MainContext::default().spawn_local(async move {
+        let mut is_connected = *channels_data.connection_rx.borrow_and_update();
+        let mut is_running   = true;
+
+        while let Some(root) = weak_root.upgrade() {
+            root.set_sensitive(is_connected && is_running);
+
+            match is_connected {
+                true  => root.remove_css_class(DISCONNECTED_CLASS),
+                false => root.add_css_class(DISCONNECTED_CLASS),
+            }
+
+            // NOTE widget isn't kept alive while waiting for the next change
+            drop(root);
+
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Some(Event::ContextStatus(status)) => is_running = contexts.iter().all(|context| status.is_running(context.name())),
+                        Some(_)                            => continue,
+                        None                               => return,
+                    },
+                    changed = channels_data.connection_rx.changed() => match changed {
+                        Ok(()) => is_connected = *channels_data.connection_rx.borrow_and_update(),
+                        Err(_) => return,
+                    },
+                }
+
+                break;
+            }
+        }
+    });
+}
+
 fn setup_layer_shell(window: &ApplicationWindow, monitor: &gtk4::gdk::Monitor) {
     window.init_layer_shell();
     window.set_layer(Layer::Top);
@@ -4,15 +4,16 @@ use std::rc::Rc;
 use gtk4::{glib::object::ObjectExt, prelude::GestureExt};
 use gtk4::glib::{MainContext, SignalHandlerId};
 use log::error;
+use rsbar_protocol::{Call, Event, EventKind};
 use crate::bar_widget::BarWidget;
 use crate::unix_sockets::ChannelsData;
-use gtk4::prelude::{BoxExt, WidgetExt, RangeExt};
+use gtk4::prelude::{BoxExt, Cast, WidgetExt, RangeExt};
 
 const EPS: f64 = 1e-5;
 
-type GetterFunction = fn(&str, &str) -> SliderFetchResult;
-type SetterFunction = fn(f64) -> String;
-type ClickFunction  = fn() -> String;
+type GetterFunction = fn(&Event) -> SliderFetchResult;
+type SetterFunction = fn(f64) -> Option<Call>;
+type ClickFunction  = fn() -> Option<Call>;
 
 pub enum SliderFetchResult {
     On,
@@ -28,6 +29,7 @@ pub struct SliderWidget {
     container:            gtk4::Box,
     max_value:            f64,
     icons:                Vec<String>,
+    events:               &'static [EventKind],
     get_value:            GetterFunction,
     set_value:            SetterFunction,
     click:                ClickFunction,
@@ -36,6 +38,7 @@ pub struct SliderWidget {
 pub struct SliderWidgetBuilder {
     max_value:           f64,
     icons:               Vec<String>,
+    events:              &'static [EventKind],
     slider_height:       i32,
     transition_duration: u32,
     set_value:           SetterFunction,
@@ -47,15 +50,16 @@ pub struct SliderWidgetBuilder {
     main_class:          String,
 }
 
-fn dummy_set(_: f64) -> String { String::new() }
-fn dummy_get(_: &str, _: &str) -> SliderFetchResult { SliderFetchResult::Value(0.0) }
-fn dummy_click() -> String { String::new() }
+fn dummy_set(_: f64) -> Option<Call> { None }
+fn dummy_get(_: &Event) -> SliderFetchResult { SliderFetchResult::Value(0.0) }
+fn dummy_click() -> Option<Call> { None }
 
 impl Default for SliderWidgetBuilder {
     fn default() -> Self {
         Self {
             max_value:           100.0,
             icons:               vec![],
+            events:              &[],
             slider_height:       100,
             transition_duration: 1000,
             set_value:           dummy_set,
@@ -80,7 +84,7 @@ impl SliderWidget {
 
     //TODO fix this BIG shit below
     fn from_builder(builder: &SliderWidgetBuilder) -> Self {
-        let slider = SliderWidget::create_slider(&builder);
+        let slider = SliderWidget::create_slider(builder);
 
         let container = gtk4::Box::new(gtk4::Orientation::Vertical, 2);
         container.add_css_class(&builder.container_class);
@@ -116,18 +120,17 @@ impl SliderWidget {
             revealer_clone_2.set_reveal_child(false);
         });
 
-        let widget = SliderWidget {
+        SliderWidget {
             slider: slider.clone(),
             label: label.clone(),
             container,
             max_value: builder.max_value,
             icons:     builder.icons.clone(),
+            events:    builder.events,
             get_value: builder.get_value,
             set_value: builder.set_value,
             click:     builder.click,
-        };
-
-        widget
+        }
     }
 
     fn create_slider(builder: &SliderWidgetBuilder) -> gtk4::Scale {
@@ -159,7 +162,7 @@ fn update_slider(slider: &gtk4::Scale, value_changed_signal: &SignalHandlerId, v
     slider.unblock_signal(value_changed_signal);
 }
 
-fn update_button(label: &gtk4::Label, icons: &Vec<String>, value: f64) {
+fn update_button(label: &gtk4::Label, icons: &[String], value: f64) {
     if value < EPS || icons.len() == 1 {
         label.set_text(&icons[0]);
     } else {
@@ -178,22 +181,33 @@ impl BarWidget for SliderWidget {
         container.append(&self.container);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
-        error!("events list must be specified manualy for each slider widget");
-        panic!();
+    fn root_widget(&self) -> gtk4::Widget {
+        self.container.clone().upcast()
     }
 
-    fn bind_channels(&self, mut channels_data: ChannelsData) {
+    fn events_list(&self) -> &'static[EventKind] {
+        if self.events.is_empty() {
+            error!("events list must be specified manualy for each slider widget");
+            panic!();
+        }
+
+        self.events
+    }
+
+    fn bind_channels(&self, channels_data: ChannelsData) {
         let widget_clone_1 = self.clone();
         let widget_clone_2 = self.clone();
 
         let call_tx_clone = channels_data.call_tx.clone();
+        let mut events    = channels_data.event_bus.subscribe(self.events_list());
 
         let gesture = gtk4::GestureClick::new();
         gesture.connect_released(move |gesture, _, _, _| {
             gesture.set_state(gtk4::EventSequenceState::Claimed);
 
-            let _ = channels_data.call_tx.send((widget_clone_1.click)());
+            if let Some(call) = (widget_clone_1.click)() {
+                channels_data.call_tx.send(call);
+            }
         });
         self.label.add_controller(gesture);
 
@@ -204,10 +218,15 @@ impl BarWidget for SliderWidget {
             let value = scale.value() / widget_clone_1.max_value;
 
             update_button(&widget_clone_1.label, &widget_clone_1.icons, value);
-            let _ = call_tx_clone.send((widget_clone_1.set_value)(value));
+            if let Some(call) = (widget_clone_1.set_value)(value) {
+                call_tx_clone.send(call);
+            }
             
             if value > EPS && !*is_on_clone.borrow() {
-                let _ = call_tx_clone.send((widget_clone_1.click)());
+                if let Some(call) = (widget_clone_1.click)() {
+                    call_tx_clone.send(call);
+                }
+
                 *is_on_clone.borrow_mut() = true;
             }
         });
@@ -215,8 +234,8 @@ impl BarWidget for SliderWidget {
         MainContext::default().spawn_local(async move {
             let mut value = 0.0;
 
-            while let Ok(event) = channels_data.event_rx.recv().await {
-                let new_value = (widget_clone_2.get_value)(&event.name, &event.value);
+            while let Some(event) = events.recv().await {
+                let new_value = (widget_clone_2.get_value)(&event);
                 
                 match new_value {
                     SliderFetchResult::On =>  {
@@ -267,6 +286,11 @@ impl SliderWidgetBuilder {
         self
     }
 
+    pub fn events(&mut self, events: &'static [EventKind]) -> &mut Self {
+        self.events = events;
+        self
+    }
+
     pub fn slider_height(&mut self, slider_height: i32) -> &mut Self {
         self.slider_height = slider_height;
         self
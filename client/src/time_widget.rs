@@ -1,8 +1,9 @@
 use crate::{bar_widget::BarWidget, unix_sockets::ChannelsData};
-use gtk4::{glib::{clone::Downgrade, MainContext}, prelude::{BoxExt, WidgetExt}};
+use gtk4::{glib::{clone::Downgrade, MainContext}, prelude::{BoxExt, Cast, WidgetExt}};
+use rsbar_protocol::{Event, EventKind};
 
-const EVENTS_LIST: &[&str] = &[
-    "time/time",
+const EVENTS_LIST: &[EventKind] = &[
+    EventKind::Time,
 ];
 
 pub struct TimeWidget {
@@ -25,21 +26,24 @@ impl BarWidget for TimeWidget {
         container.append(&self.label);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
+    fn root_widget(&self) -> gtk4::Widget {
+        self.label.clone().upcast()
+    }
+
+    fn events_list(&self) -> &'static[EventKind] {
         EVENTS_LIST
     }
     
-    fn bind_channels(&self, mut channels_data: ChannelsData) {
+    fn bind_channels(&self, channels_data: ChannelsData) {
         let weak_label = self.label.downgrade();
+        let mut events = channels_data.event_bus.subscribe(EVENTS_LIST);
 
         MainContext::default().spawn_local(async move {
 
-            while let Ok(event) = channels_data.event_rx.recv().await {
-                if event.name != EVENTS_LIST[0] {
-                    continue;
+            while let Some(event) = events.recv().await {
+                if let Event::Time(time) = event {
+                    weak_label.upgrade().unwrap().set_text(&time);
                 }
-
-                weak_label.upgrade().unwrap().set_text(&event.value);
             }
         });
     }
@@ -1,154 +1,320 @@
-use std::time::Duration;
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, pin::pin, time::Duration};
 
 use log::{error, info, warn};
-use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixStream}, time::interval};
 
-const RECONNECTION_TIMEOUT: u64 = 1000;
+use crate::{event_bus::EventBus, tokio_runtime::tokio_runtime};
+use rsbar_protocol::{Call, CallReply, ContextEvent, ErrorCode, EventAddress, EventKind, ProtocolVersion, SocketPaths, FRAME_DELIMITER};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixStream}, sync::{mpsc, oneshot, watch}, time::{sleep, timeout}};
 
-pub struct ChannelsData {
-    pub event_subscription_tx: tokio::sync::mpsc::Sender<String>,
-    pub event_rx: tokio::sync::broadcast::Receiver<RsbarEvent>,
-    pub call_tx:  tokio::sync::broadcast::Sender<String>,
-}
+// Reconnection timeout is doubled after each failed attempt
+const MIN_RECONNECTION_TIMEOUT: u64 = 250;
+const MAX_RECONNECTION_TIMEOUT: u64 = 8000;
+const HANDSHAKE_TIMEOUT:        u64 = 500;
 
-impl Clone for ChannelsData {
-    fn clone(&self) -> Self {
-        ChannelsData {
-            event_subscription_tx: self.event_subscription_tx.clone(),
-            event_rx:              self.event_rx.resubscribe(),
-            call_tx:               self.call_tx.clone()
-        }
-    }
+#[derive(Clone)]
+pub struct ChannelsData {
+    pub event_bus:     EventBus,
+    pub call_tx:       CallSender,
+    // true while the event socket is connected, values of the widgets are stale otherwise
+    pub connection_rx: watch::Receiver<bool>,
 }
 
 struct UnixSocketConnection {
     write_stream: OwnedWriteHalf,
     reader:       BufReader<OwnedReadHalf>,
+    version:      ProtocolVersion,
+}
+
+struct RsbarCall {
+    request:  Call,
+    reply_tx: oneshot::Sender<CallReply>,
 }
 
 #[derive(Clone)]
-pub struct RsbarEvent {
-    pub name:    String,
-    pub value:   String,
+pub struct CallSender {
+    call_tx: mpsc::UnboundedSender<RsbarCall>,
+}
+
+impl CallSender {
+    // Fire and forget: error replies are only logged
+    pub fn send(&self, request: Call) {
+        let reply_rx = self.call(request.clone());
+
+        tokio_runtime().spawn(async move {
+            if let Ok(CallReply::Error(code, message)) = reply_rx.await {
+                warn!("Call {request} failed ({code}): {message}");
+            }
+        });
+    }
+
+    // Reply is routed back through the returned receiver
+    pub fn call(&self, request: Call) -> oneshot::Receiver<CallReply> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        if self.call_tx.send(RsbarCall { request, reply_tx }).is_err() {
+            error!("Call task is not running");
+        }
+
+        reply_rx
+    }
 }
 
-async fn connect_to_unix_socket(socket_path: &str) -> tokio::io::Result<UnixSocketConnection> {
-    let mut reconnection_timeout = interval(Duration::from_millis(RECONNECTION_TIMEOUT));
+async fn connect_to_unix_socket(socket_path: &Path) -> UnixSocketConnection {
+    let mut reconnection_timeout = MIN_RECONNECTION_TIMEOUT;
 
     loop {
-        info!("Waiting for connection to the {socket_path}");
-
-        let server_stream = UnixStream::connect(socket_path).await;
-
-        match server_stream {
-            Ok(stream) => {
-                let (read_stream, write_stream) = stream.into_split();
-                let reader                      = BufReader::new(read_stream);
-                
-                return Ok(UnixSocketConnection {
-                    write_stream,
-                    reader
-                });
+        info!("Waiting for connection to the {}", socket_path.display());
+
+        match open_connection(socket_path).await {
+            Ok(connection) => {
+                info!("Using protocol version {} for {}", connection.version as u32, socket_path.display());
+
+                return connection;
             },
             Err(error) => {
-                warn!("Failed to connect. Retrying: {error}");
-                reconnection_timeout.tick().await;
-                continue;
+                warn!("Failed to connect. Retrying in {reconnection_timeout} ms: {error}");
+
+                sleep(Duration::from_millis(reconnection_timeout)).await;
+                reconnection_timeout = (reconnection_timeout * 2).min(MAX_RECONNECTION_TIMEOUT);
             },
         }
     }
 }
 
+async fn open_connection(socket_path: &Path) -> tokio::io::Result<UnixSocketConnection> {
+    let (read_stream, write_stream) = UnixStream::connect(socket_path).await?.into_split();
+
+    let mut connection = UnixSocketConnection {
+        write_stream,
+        reader:  BufReader::new(read_stream),
+        version: ProtocolVersion::V1Text,
+    };
+
+    connection.version = negotiate_protocol(&mut connection).await?;
+
+    Ok(connection)
+}
+
+// Servers that don't support negotiation never answer the hello request
+async fn negotiate_protocol(connection: &mut UnixSocketConnection) -> tokio::io::Result<ProtocolVersion> {
+    send_message(&mut connection.write_stream, &ProtocolVersion::hello_request()).await?;
+
+    let mut reply_vec = Vec::new();
+
+    match timeout(Duration::from_millis(HANDSHAKE_TIMEOUT), read_frame(&mut connection.reader, &mut reply_vec)).await {
+        Ok(reply) => Ok(ProtocolVersion::from_hello(&reply?).unwrap_or(ProtocolVersion::V1Text)),
+        Err(_)    => Ok(ProtocolVersion::V1Text),
+    }
+}
+
 async fn send_message(stream: &mut OwnedWriteHalf, message: &str) -> tokio::io::Result<()> {
-    stream.write(message.as_bytes()).await?;
-    stream.write(b"\0").await?;
+    stream.write_all(message.as_bytes()).await?;
+    stream.write_all(&[FRAME_DELIMITER]).await?;
     stream.flush().await?;
 
     Ok(())
 }
 
-pub async fn setup_unix_sockets() -> tokio::io::Result<ChannelsData> {
+// Sockets are connected in the background, so the bar is shown (as disconnected) even if the daemon isn't running yet
+pub fn setup_unix_sockets(paths: &SocketPaths) -> ChannelsData {
     info!("Connecting to sockets");
 
-    let mut event_socket_data = connect_to_unix_socket("/tmp/rsbar_event.sock").await?;
-    let mut call_socket_data  = connect_to_unix_socket("/tmp/rsbar_call.sock").await?;
-
-    let mut event_reader = event_socket_data.reader;
-    
-    let (event_subscription_tx, mut event_subscription_rx) = tokio::sync::mpsc::channel::<String>(32);
-    let (event_tx, event_rx)   = tokio::sync::broadcast::channel::<RsbarEvent>(32);
-    let (call_tx, mut call_rx) = tokio::sync::broadcast::channel::<String>(32);
-
-    
-    tokio::spawn(async move {
-        while let Some(new_event) = event_subscription_rx.recv().await {
-            info!("Subscribing to event: {}", new_event);
-            
-            if let Err(error_info) = send_message(&mut event_socket_data.write_stream, new_event.as_str()).await {
-                warn!("Error occuried while subscribing to event {new_event}: {error_info}");
+    let (event_subscription_tx, event_subscription_rx) = mpsc::unbounded_channel::<EventKind>();
+    let (call_tx, call_rx)                             = mpsc::unbounded_channel::<RsbarCall>();
+    let (connection_tx, connection_rx)                 = watch::channel(false);
+
+    let event_bus = EventBus::new(event_subscription_tx);
+
+    tokio_runtime().spawn(run_event_connection(paths.event_socket(), event_subscription_rx, event_bus.clone(), connection_tx));
+    tokio_runtime().spawn(run_call_connection(paths.call_socket(), call_rx));
+
+    ChannelsData {
+        event_bus,
+        call_tx: CallSender { call_tx },
+        connection_rx,
+    }
+}
+
+// Keeps the event socket connected. Subscriptions are remembered and sent again after each reconnection
+async fn run_event_connection(socket_path: PathBuf, mut event_subscription_rx: mpsc::UnboundedReceiver<EventKind>, event_bus: EventBus, connection_tx: watch::Sender<bool>) {
+    // Widgets of each window request the same events, so identical requests are merged
+    let mut subscribed_events = HashSet::new();
+
+    loop {
+        // NOTE subscriptions made while the daemon is unavailable are sent once it's connected
+        let mut connection = {
+            let mut connecting = pin!(connect_to_unix_socket(&socket_path));
+
+            loop {
+                tokio::select! {
+                    connection = &mut connecting => break connection,
+                    new_event = event_subscription_rx.recv() => match new_event {
+                        Some(new_event) => { subscribed_events.insert(new_event); },
+                        None            => return,
+                    },
+                }
             }
+        };
+
+        connection_tx.send_replace(true);
+
+        if let Err(error) = handle_event_connection(&mut connection, &mut subscribed_events, &mut event_subscription_rx, &event_bus).await {
+            error!("Event socket is disconnected: {error}");
         }
-    });
 
-    tokio::spawn(async move {
-        let mut event_vec = Vec::new();
+        connection_tx.send_replace(false);
+    }
+}
+
+// Returns once the connection is lost
+async fn handle_event_connection(connection: &mut UnixSocketConnection, subscribed_events: &mut HashSet<EventKind>, event_subscription_rx: &mut mpsc::UnboundedReceiver<EventKind>, event_bus: &EventBus) -> tokio::io::Result<()> {
+    let version = connection.version;
 
-        while event_reader.read_until(b'\0', &mut event_vec).await.unwrap() > 0 {
-            event_vec.pop();
+    for event in subscribed_events.iter() {
+        info!("Subscribing to event: {}", event);
 
-            // TODO log error
-            let event = String::from_utf8(event_vec.clone()).unwrap();
+        send_message(&mut connection.write_stream, &version.encode_subscription(*event)).await?;
+    }
 
-            info!("Got event: {event}");
+    let mut event_vec = Vec::new();
+    // Sequence number of the last received value of each event, sequences start over with each daemon
+    let mut last_sequences = HashMap::new();
 
-            let split_result = split_at_nth_char_ex(&event, '/', 1);
-        
-            if split_result.is_none() {
-                error!("Bad event format: {event}");
-                continue;
-            }
+    loop {
+        tokio::select! {
+            Some(new_event) = event_subscription_rx.recv() => {
+                if !subscribed_events.insert(new_event) {
+                    continue;
+                }
 
-            let (name, value) = split_result.unwrap();
+                info!("Subscribing to event: {}", new_event);
 
-            let event_struct = RsbarEvent {
-                name:  name.to_string(),
-                value: value.to_string(),                
-            };
+                // NOTE failed subscription is kept, it's sent again after reconnection
+                send_message(&mut connection.write_stream, &version.encode_subscription(new_event)).await?;
+            },
+            event = read_frame(&mut connection.reader, &mut event_vec) => {
+                let event = event?;
+
+                info!("Got event: {event}");
+
+                let event_struct = match version.decode_event(&event) {
+                    Ok(event_struct) => event_struct,
+                    Err(error)       => {
+                        error!("{error}");
+                        continue;
+                    },
+                };
 
-            let _ = event_tx.send(event_struct);
+                // NOTE snapshot is delivered as a regular event, so the gap is closed by its value
+                if is_gap(&mut last_sequences, &event_struct) {
+                    warn!("Missed some values of {}, requesting a snapshot", event_struct.address());
 
-            event_vec.clear();
+                    send_message(&mut connection.write_stream, &version.encode_snapshot(event_struct.address())).await?;
+                }
+
+                event_bus.publish(event_struct.event);
+            },
         }
-    });
+    }
+}
+
+// Unstamped events (v1 protocol) are never reported. Values coalesced by the daemon aren't a gap
+fn is_gap(last_sequences: &mut HashMap<EventAddress, u64>, event: &ContextEvent) -> bool {
+    let Some(stamp) = event.stamp else {
+        return false;
+    };
 
-    tokio::spawn(async move {
-        while let Some(call) = call_rx.recv().await.ok() {
-            info!("Calling remote procedure: {call}");
+    let last_seq = last_sequences.entry(event.address()).or_insert(stamp.seq);
+    let is_gap   = stamp.seq.saturating_sub(stamp.coalesced) > *last_seq + 1;
+
+    *last_seq = (*last_seq).max(stamp.seq);
+
+    is_gap
+}
 
-            // TODO process errors
-            if let Err(error_info) = send_message(&mut call_socket_data.write_stream, call.as_str()).await {
-                warn!("Error occuried while calling {call}: {error_info}");
+// Calls made while the daemon is disconnected are rejected rather than queued,
+// so stale actions (e.g. slider moves) aren't performed after reconnection
+async fn run_call_connection(socket_path: PathBuf, mut call_rx: mpsc::UnboundedReceiver<RsbarCall>) {
+    loop {
+        let mut connection = {
+            let mut connecting = pin!(connect_to_unix_socket(&socket_path));
+
+            loop {
+                tokio::select! {
+                    connection = &mut connecting => break connection,
+                    call = call_rx.recv() => match call {
+                        Some(call) => reject_call(call),
+                        None       => return,
+                    },
+                }
             }
+        };
+
+        match handle_call_connection(&mut connection, &mut call_rx).await {
+            Ok(())     => return,
+            Err(error) => error!("Call socket is disconnected: {error}"),
+        }
+    }
+}
+
+// Returns an error once the connection is lost, or Ok when the call channel is closed
+async fn handle_call_connection(connection: &mut UnixSocketConnection, call_rx: &mut mpsc::UnboundedReceiver<RsbarCall>) -> tokio::io::Result<()> {
+    let mut reply_vec = Vec::new();
+
+    loop {
+        // NOTE daemon never writes to an idle call socket, so the read ends only when the socket is closed
+        let call = tokio::select! {
+            call  = call_rx.recv() => call,
+            frame = read_frame(&mut connection.reader, &mut reply_vec) => {
+                warn!("Unexpected frame on the call socket: {}", frame?);
+                continue;
+            },
+        };
+
+        let Some(call) = call else {
+            return Ok(());
+        };
+
+        info!("Calling remote procedure: {}", call.request);
+
+        match send_call(connection, &call.request, &mut reply_vec).await {
+            Ok(reply)  => { let _ = call.reply_tx.send(reply); },
+            Err(error) => {
+                warn!("Unable to get reply for {}: {error}", call.request);
+                reject_call(call);
+
+                return Err(error);
+            },
         }
-    });
+    }
+}
+
+async fn send_call(connection: &mut UnixSocketConnection, request: &Call, reply_vec: &mut Vec<u8>) -> tokio::io::Result<CallReply> {
+    let version = connection.version;
+
+    send_message(&mut connection.write_stream, &version.encode_call(request.clone())).await?;
+
+    let reply = read_frame(&mut connection.reader, reply_vec).await?;
 
-    Ok(ChannelsData {
-        event_subscription_tx,
-        event_rx,
-        call_tx,
-    })
+    version.decode_reply(&reply)
 }
 
-fn split_at_nth_char(s: &str, p: char, n: usize) -> Option<(&str, &str)> {
-    s.match_indices(p).nth(n).map(|(index, _)| s.split_at(index))
+fn reject_call(call: RsbarCall) {
+    let _ = call.reply_tx.send(CallReply::Error(ErrorCode::Failed, "Daemon is not connected".to_string()));
 }
 
-fn split_at_nth_char_ex(s: &str, p: char, n: usize) -> Option<(&str, &str)> {
-    split_at_nth_char(s, p, n).map(|(left, right)| {
-        (
-            left,
-            // Trim 1 character.
-            &right[right.char_indices().nth(1).unwrap().0..],
-        )
-    })
+// NOTE read_frame is cancel safe: partially read frame is kept in the frame vector
+async fn read_frame(reader: &mut BufReader<OwnedReadHalf>, frame_vec: &mut Vec<u8>) -> tokio::io::Result<String> {
+    if reader.read_until(FRAME_DELIMITER, frame_vec).await? == 0 {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Socket is closed"));
+    }
+
+    if frame_vec.last() == Some(&FRAME_DELIMITER) {
+        frame_vec.pop();
+    }
+
+    let frame = String::from_utf8_lossy(frame_vec).into_owned();
+    frame_vec.clear();
+
+    Ok(frame)
 }
@@ -1,3 +1,5 @@
+use rsbar_protocol::{Call, Event, EventKind};
+
 use crate::bar_widget::BarWidget;
 use crate::slider_widget::{SliderFetchResult, SliderWidget};
 use crate::unix_sockets::ChannelsData;
@@ -6,9 +8,9 @@ const MAX_VOLUME:    f64 = 100.0;
 const SLIDER_HEIGHT: i32 = 100;
 const VOLUME_ICONS: [&str; 4] = ["󰖁", "󰕿", "󰖀", "󰕾"];
 
-const EVENTS_LIST: &[&str] = &[
-    "volume/volume",
-    "volume/isMuted",
+const EVENTS_LIST: &[EventKind] = &[
+    EventKind::Volume,
+    EventKind::VolumeMuted,
 ];
 
 #[derive(Clone)]
@@ -21,6 +23,7 @@ impl VolumeWidget {
         VolumeWidget {
             slider_widget: SliderWidget::builder()
                 .icons(&VOLUME_ICONS.map(|x| x.to_string()))
+                .events(EVENTS_LIST)
                 .transition_duration(duration)
                 .slider_height(SLIDER_HEIGHT)
                 .max_value(MAX_VOLUME)
@@ -41,7 +44,11 @@ impl BarWidget for VolumeWidget {
         self.slider_widget.bind_widget(container);
     }
 
-    fn events_list(&self) -> &'static[&'static str] {
+    fn root_widget(&self) -> gtk4::Widget {
+        self.slider_widget.root_widget()
+    }
+
+    fn events_list(&self) -> &'static[EventKind] {
         EVENTS_LIST
     }
 
@@ -51,30 +58,19 @@ impl BarWidget for VolumeWidget {
 
 }
 
-fn toggle_mute() -> String {
-    "volume/toggleMute/".to_string()
+fn toggle_mute() -> Option<Call> {
+    Some(Call::ToggleMute)
 }
 
-fn set_system_volume(volume: f64) -> String {
-    format!("volume/setVolume/{}", (volume * MAX_VOLUME) as u32)
+fn set_system_volume(volume: f64) -> Option<Call> {
+    Some(Call::SetVolume((volume * MAX_VOLUME) as u32))
 }
 
-fn get_system_volume(name: &str, value: &str) -> SliderFetchResult {
-    if name == EVENTS_LIST[0] {
-        let value_float = value.parse::<f64>();
-        
-        if value_float.is_err() {
-            return SliderFetchResult::None;
-        }
-        
-        return SliderFetchResult::Value(value_float.unwrap() / MAX_VOLUME);
-    } else if name == EVENTS_LIST[1] {
-        return match value {
-            "false" => SliderFetchResult::On,
-            "true"  => SliderFetchResult::Off,
-            _       => SliderFetchResult::None,
-        };
+fn get_system_volume(event: &Event) -> SliderFetchResult {
+    match event {
+        Event::Volume(volume)     => SliderFetchResult::Value(*volume as f64 / MAX_VOLUME),
+        Event::VolumeMuted(false) => SliderFetchResult::On,
+        Event::VolumeMuted(true)  => SliderFetchResult::Off,
+        _                         => SliderFetchResult::None,
     }
-
-    SliderFetchResult::None
 }
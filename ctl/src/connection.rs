@@ -0,0 +1,73 @@
+use std::{io::ErrorKind, path::Path, time::Duration};
+
+use rsbar_protocol::{ProtocolVersion, FRAME_DELIMITER};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixStream}, time::timeout};
+
+const HANDSHAKE_TIMEOUT: u64 = 500;
+
+// Connection to one of the daemon sockets with the negotiated protocol version
+pub struct Connection {
+    reader:       BufReader<OwnedReadHalf>,
+    write_stream: OwnedWriteHalf,
+    frame_buffer: Vec<u8>,
+    version:      ProtocolVersion,
+}
+
+impl Connection {
+    pub async fn open(socket: &Path) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(socket).await
+            .map_err(|error| std::io::Error::new(error.kind(), format!("Unable to connect to {}: {error}", socket.display())))?;
+
+        let (read_stream, write_stream) = stream.into_split();
+
+        let mut connection = Connection {
+            reader:       BufReader::new(read_stream),
+            frame_buffer: Vec::new(),
+            version:      ProtocolVersion::V1Text,
+            write_stream,
+        };
+
+        connection.negotiate_protocol().await?;
+
+        Ok(connection)
+    }
+
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    pub async fn send(&mut self, frame: &str) -> std::io::Result<()> {
+        self.write_stream.write_all(frame.as_bytes()).await?;
+        self.write_stream.write_all(&[FRAME_DELIMITER]).await?;
+        self.write_stream.flush().await
+    }
+
+    // Returns None once the daemon closes the connection
+    pub async fn recv(&mut self) -> std::io::Result<Option<String>> {
+        self.frame_buffer.clear();
+
+        if self.reader.read_until(FRAME_DELIMITER, &mut self.frame_buffer).await? == 0 {
+            return Ok(None);
+        }
+
+        if self.frame_buffer.pop() != Some(FRAME_DELIMITER) {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection was closed in the middle of a frame"));
+        }
+
+        Ok(Some(String::from_utf8_lossy(&self.frame_buffer).into_owned()))
+    }
+
+    // Servers that don't support negotiation never answer the hello request
+    async fn negotiate_protocol(&mut self) -> std::io::Result<()> {
+        self.send(&ProtocolVersion::hello_request()).await?;
+
+        self.version = match timeout(Duration::from_millis(HANDSHAKE_TIMEOUT), self.recv()).await {
+            Ok(Ok(Some(reply))) => ProtocolVersion::from_hello(&reply).unwrap_or(ProtocolVersion::V1Text),
+            Ok(Ok(None))        => return Err(std::io::Error::new(ErrorKind::ConnectionRefused, "Daemon closed the connection")),
+            Ok(Err(error))      => return Err(error),
+            Err(_)              => ProtocolVersion::V1Text,
+        };
+
+        Ok(())
+    }
+}
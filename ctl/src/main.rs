@@ -0,0 +1,290 @@
+mod connection;
+
+use std::{collections::BTreeMap, io::ErrorKind, process::ExitCode};
+
+use connection::Connection;
+use rsbar_protocol::{take_flag, value_to_text, Backpressure, Call, CallReply, ContextCall, ContextDescription, ContextEvent, ContextState, Event, EventAddress, EventKind, EventPattern, HistoryQuery, HistorySample, ProtocolVersion, SocketPaths};
+use serde_json::{json, Value};
+
+const USAGE: &str = "\
+Usage: rsbarctl [--socket-dir <path>] [--instance <name>] [--json] [--backpressure <policy>] <command>
+
+Commands:
+  call <context>/<procedure> [args]   invoke a procedure and print its result
+  watch <pattern>...                  print the events matching the patterns as they are triggered
+  get <pattern>...                    print the current values of the events
+  history <event> [<last> [<step>]]   print the recorded values of the last milliseconds (averaged over the steps)
+  list                                describe the running contexts with their events and procedures
+  status                              print the state of each context
+
+Values are printed as text, or as json with the --json flag (one event per line for watch).
+The --backpressure flag sets the queue policy of watch: coalesce, dropOldest or disconnect.
+Example: rsbarctl call volume/setVolume 40";
+
+const JSON_FLAG:         &str = "--json";
+const BACKPRESSURE_FLAG: &str = "--backpressure";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(())     => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("rsbarctl: {error}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+async fn run() -> std::io::Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let paths = SocketPaths::from_args(&mut args)?;
+    let json  = take_switch(&mut args, JSON_FLAG);
+
+    let backpressure = take_flag(&mut args, BACKPRESSURE_FLAG)?.map(|policy| policy.parse::<Backpressure>()).transpose()?;
+
+    let Some((command, args)) = args.split_first() else {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Missing command\n\n{USAGE}")));
+    };
+
+    match (command.as_str(), args) {
+        ("call", [target, call_args @ ..])          => call(&paths, target, &call_args.join(" "), json).await,
+        ("watch", patterns) if !patterns.is_empty() => watch(&paths, patterns, backpressure, json).await,
+        ("get", patterns) if !patterns.is_empty()   => get(&paths, patterns, json).await,
+        ("history", [event, range @ ..])            => history(&paths, event, range, json).await,
+        ("list", [])                                => list(&paths, json).await,
+        ("status", [])                              => status(&paths, json).await,
+        ("help" | "--help" | "-h", _)               => {
+            println!("{USAGE}");
+            Ok(())
+        },
+        _ => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Unknown command or bad arguments: {command}\n\n{USAGE}"))),
+    }
+}
+
+async fn call(paths: &SocketPaths, target: &str, args: &str, json: bool) -> std::io::Result<()> {
+    if !target.contains('/') {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Bad procedure name: {target} (expected <context>/<procedure>)")));
+    }
+
+    // NOTE request is parsed locally, so typos are reported without connecting to the daemon
+    let request = ProtocolVersion::V1Text.decode_call(&format!("{target}/{args}"))?;
+
+    match invoke(paths, request).await? {
+        Some(value) if json => println!("{value}"),
+        Some(value)         => println!("{}", value_to_text(&value)),
+        None                => {},
+    }
+
+    Ok(())
+}
+
+// Streams the events until the daemon shuts down
+async fn watch(paths: &SocketPaths, patterns: &[String], backpressure: Option<Backpressure>, json: bool) -> std::io::Result<()> {
+    let patterns = patterns.iter().map(|pattern| pattern.parse::<EventPattern>()).collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut connection = Connection::open(&paths.event_socket()).await?;
+    let version = connection.version();
+
+    // NOTE policy is set before subscribing, so it applies to the last values too
+    if let Some(policy) = backpressure {
+        connection.send(&version.encode_backpressure(policy)).await?;
+    }
+
+    for pattern in patterns {
+        connection.send(&version.encode_subscription(pattern)).await?;
+    }
+
+    while let Some(frame) = connection.recv().await? {
+        let event = version.decode_event(&frame)?;
+
+        match json {
+            true  => println!("{}", event_to_json(&event)),
+            false => println!("{}", ProtocolVersion::V1Text.encode_event(&event)),
+        }
+    }
+
+    Ok(())
+}
+
+// Stamps are printed only if the daemon sends them (v2 protocol)
+fn event_to_json(event: &ContextEvent) -> Value {
+    let mut json = json!({ "context": event.context.to_string(), "event": event.event.kind().name(), "value": event.event.value_to_json() });
+
+    if let Some(stamp) = event.stamp {
+        json["seq"]       = stamp.seq.into();
+        json["timestamp"] = stamp.timestamp.into();
+
+        if stamp.coalesced > 0 {
+            json["coalesced"] = stamp.coalesced.into();
+        }
+    }
+
+    json
+}
+
+// Value of a single event is printed as is, values of several events are printed as "<context>/<event>/<value>" lines
+async fn get(paths: &SocketPaths, patterns: &[String], json: bool) -> std::io::Result<()> {
+    if let [pattern] = patterns {
+        if let Ok(address) = pattern.parse::<EventAddress>() {
+            let event = query_event(paths, address).await?;
+
+            match json {
+                true  => println!("{}", event.value_to_json()),
+                false => println!("{}", event.value_to_text()),
+            }
+
+            return Ok(());
+        }
+    }
+
+    let patterns = patterns.iter().map(|pattern| pattern.parse::<EventPattern>()).collect::<std::io::Result<Vec<_>>>()?;
+    let values   = query(paths, patterns).await?;
+
+    if json {
+        println!("{}", Value::Object(values));
+        return Ok(());
+    }
+
+    // NOTE events which haven't been triggered yet are skipped
+    for (name, value) in values.into_iter().filter(|(_, value)| !value.is_null()) {
+        let event = name.parse::<EventAddress>()?.kind.value_from_json(value)?;
+
+        println!("{name}/{}", event.value_to_text());
+    }
+
+    Ok(())
+}
+
+// Samples are printed as "<timestamp> <value>" lines
+async fn history(paths: &SocketPaths, event: &str, range: &[String], json: bool) -> std::io::Result<()> {
+    if range.len() > 2 {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Too many history arguments\n\n{USAGE}")));
+    }
+
+    let range = range.iter()
+        .map(|value| value.parse::<u64>().map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, format!("Bad number of milliseconds: {value}"))))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let query = HistoryQuery {
+        last: range.first().copied(),
+        step: range.get(1).copied(),
+        ..HistoryQuery::new(event.parse()?)
+    };
+
+    let samples = invoke(paths, Call::History(query)).await?.unwrap_or_default();
+
+    if json {
+        println!("{samples}");
+        return Ok(());
+    }
+
+    for sample in serde_json::from_value::<Vec<HistorySample>>(samples)? {
+        println!("{} {}", sample.timestamp, sample.value);
+    }
+
+    Ok(())
+}
+
+async fn list(paths: &SocketPaths, json: bool) -> std::io::Result<()> {
+    let descriptions = invoke(paths, Call::Describe).await?.unwrap_or_default();
+
+    if json {
+        println!("{descriptions}");
+        return Ok(());
+    }
+
+    let descriptions: BTreeMap<String, ContextDescription> = serde_json::from_value(descriptions)?;
+    let mut rows = Vec::new();
+
+    for (context, description) in descriptions {
+        rows.push(["context".to_string(), context.clone(), String::new(), description.doc]);
+
+        for event in description.events {
+            rows.push(["event".to_string(), format!("{context}/{}", event.name), event.value.to_string(), event.doc]);
+        }
+
+        for procedure in description.procedures {
+            let args = procedure.args.map(|args| args.to_string()).unwrap_or_default();
+
+            rows.push(["procedure".to_string(), format!("{context}/{}", procedure.name), args, procedure.doc]);
+        }
+    }
+
+    let width = |column: usize| rows.iter().map(|row: &[String; 4]| row[column].len()).max().unwrap_or(0);
+    let (kind_width, name_width, value_width) = (width(0), width(1), width(2));
+
+    for [kind, name, value, doc] in &rows {
+        println!("{kind:kind_width$}  {name:name_width$}  {value:value_width$}  {doc}");
+    }
+
+    Ok(())
+}
+
+async fn status(paths: &SocketPaths, json: bool) -> std::io::Result<()> {
+    let event = query_event(paths, EventKind::ContextStatus.into()).await?;
+
+    let Event::ContextStatus(status) = &event else {
+        unreachable!("Only contextStatus events are fetched");
+    };
+
+    if json {
+        println!("{}", event.value_to_json());
+        return Ok(());
+    }
+
+    let width = status.0.keys().map(|name| name.len()).max().unwrap_or(0);
+
+    for (name, state) in &status.0 {
+        match state {
+            ContextState::Starting           => println!("{name:width$}  starting"),
+            ContextState::Running            => println!("{name:width$}  running"),
+            ContextState::Degraded { error } => println!("{name:width$}  degraded: {error}"),
+        }
+    }
+
+    Ok(())
+}
+
+// Sends the call and returns its result, error replies are returned as errors
+async fn invoke(paths: &SocketPaths, request: impl Into<ContextCall>) -> std::io::Result<Option<Value>> {
+    let mut connection = Connection::open(&paths.call_socket()).await?;
+    let version = connection.version();
+
+    connection.send(&version.encode_call(request)).await?;
+
+    let Some(reply) = connection.recv().await? else {
+        return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Daemon closed the connection without a reply"));
+    };
+
+    match version.decode_reply(&reply)? {
+        CallReply::Ok(value)            => Ok(value),
+        CallReply::Error(code, message) => Err(std::io::Error::other(format!("{code}: {message}"))),
+    }
+}
+
+// Current values of the matching events: { "<context>/<event>": <value or null> }
+async fn query(paths: &SocketPaths, patterns: Vec<EventPattern>) -> std::io::Result<serde_json::Map<String, Value>> {
+    match invoke(paths, Call::Query(patterns)).await? {
+        Some(Value::Object(values)) => Ok(values),
+        reply => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Unexpected query result: {}", reply.unwrap_or_default()))),
+    }
+}
+
+async fn query_event(paths: &SocketPaths, address: EventAddress) -> std::io::Result<Event> {
+    let value = query(paths, vec![address.clone().into()]).await?.remove(&address.to_string()).unwrap_or_default();
+
+    if value.is_null() {
+        return Err(std::io::Error::new(ErrorKind::NotFound, format!("{address} hasn't been triggered yet")));
+    }
+
+    address.kind.value_from_json(value)
+}
+
+// Removes the flag without a value from the arguments
+fn take_switch(args: &mut Vec<String>, flag: &str) -> bool {
+    let count = args.len();
+    args.retain(|arg| arg != flag);
+
+    args.len() != count
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use rsbar_protocol::{ContextId, ContextKind};
+use serde::Deserialize;
+use tokio::net::UnixStream;
+
+// Access policy ([access] section of the daemon config).
+// The user running the daemon always has full access. Other users are allowed to read events
+// or to invoke procedures either for all of the contexts or for the specified ones:
+//
+// [access]
+// read = ["uid:1001", "gid:100"]
+// call = ["uid:1001"]
+//
+// [access.contexts.brightness]
+// call = ["uid:1002"]
+//
+// Principals are "uid:<uid>", "gid:<gid>" or "*" (anyone). Permission to call implies permission to read.
+// Rules of a context kind (e.g. "battery") apply to all of its instances, rules of an instance (e.g. "battery@BAT1") only to that instance
+// NOTE effective uid of the daemon doesn't change, so it's read only once
+static OWNER_UID: Lazy<u32> = Lazy::new(|| unsafe { libc::geteuid() });
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessPolicy {
+    read:     Vec<Principal>,
+    call:     Vec<Principal>,
+    // NOTE unknown contexts are rejected, so a typo doesn't silently leave a context without its rules
+    contexts: HashMap<ContextId, AccessRule>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AccessRule {
+    read: Vec<Principal>,
+    call: Vec<Principal>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+enum Principal {
+    Uid(u32),
+    Gid(u32),
+    Anyone,
+}
+
+// Credentials of the connected client (SO_PEERCRED)
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    uid:      u32,
+    gid:      u32,
+    is_owner: bool,
+}
+
+impl AccessPolicy {
+    pub fn can_read(&self, peer: &Peer, context: &ContextId) -> bool {
+        self.can_call(peer, context) || self.matches(peer, context, |rule| &rule.read, &self.read)
+    }
+
+    pub fn can_call(&self, peer: &Peer, context: &ContextId) -> bool {
+        peer.is_owner || self.matches(peer, context, |rule| &rule.call, &self.call)
+    }
+
+    // Clients without any permissions are disconnected right away
+    pub fn has_any_access(&self, peer: &Peer) -> bool {
+        let instances = self.contexts.keys().cloned();
+
+        ContextKind::ALL.iter().map(|kind| ContextId::from(*kind)).chain(instances).any(|context| self.can_read(peer, &context))
+    }
+
+    // Sockets are accessible by the other users only if the policy allows it
+    pub fn allows_others(&self) -> bool {
+        let rules = self.contexts.values().flat_map(|rule| rule.read.iter().chain(rule.call.iter()));
+
+        self.read.iter().chain(self.call.iter()).chain(rules).next().is_some()
+    }
+
+    fn matches(&self, peer: &Peer, context: &ContextId, rule_principals: fn(&AccessRule) -> &Vec<Principal>, principals: &[Principal]) -> bool {
+        let kind_principals     = self.contexts.get(&ContextId::from(context.kind())).map(rule_principals).into_iter().flatten();
+        let instance_principals = context.instance().and_then(|_| self.contexts.get(context)).map(rule_principals).into_iter().flatten();
+
+        principals.iter().chain(kind_principals).chain(instance_principals).any(|principal| principal.matches(peer))
+    }
+}
+
+impl Principal {
+    fn matches(&self, peer: &Peer) -> bool {
+        match self {
+            Principal::Uid(uid) => peer.uid == *uid,
+            Principal::Gid(gid) => peer.gid == *gid,
+            Principal::Anyone   => true,
+        }
+    }
+}
+
+impl TryFrom<String> for Principal {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        if string == "*" {
+            return Ok(Principal::Anyone);
+        }
+
+        let parse_id = |id: &str| id.parse::<u32>().map_err(|_| format!("Bad principal: {string}"));
+
+        match string.split_once(':') {
+            Some(("uid", uid)) => Ok(Principal::Uid(parse_id(uid)?)),
+            Some(("gid", gid)) => Ok(Principal::Gid(parse_id(gid)?)),
+            _ => Err(format!("Bad principal: {string} (expected \"uid:<uid>\", \"gid:<gid>\" or \"*\")")),
+        }
+    }
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> std::io::Result<Self> {
+        let credentials = stream.peer_cred()?;
+
+        Ok(Peer {
+            uid:      credentials.uid(),
+            gid:      credentials.gid(),
+            is_owner: credentials.uid() == owner_uid(),
+        })
+    }
+
+    // Peer runs as the same user as the daemon
+    pub fn is_owner(&self) -> bool {
+        self.is_owner
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "uid {} (gid {})", self.uid, self.gid)
+    }
+}
+
+// Uid of the user running the daemon
+pub fn owner_uid() -> u32 {
+    *OWNER_UID
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(uid: u32, gid: u32) -> Peer {
+        Peer { uid, gid, is_owner: false }
+    }
+
+    fn policy(toml: &str) -> AccessPolicy {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn context(id: &str) -> ContextId {
+        id.parse().unwrap()
+    }
+
+    #[test]
+    fn principals_are_parsed() {
+        assert_eq!(Principal::try_from("uid:1001".to_string()), Ok(Principal::Uid(1001)));
+        assert_eq!(Principal::try_from("gid:100".to_string()), Ok(Principal::Gid(100)));
+        assert_eq!(Principal::try_from("*".to_string()), Ok(Principal::Anyone));
+
+        for principal in ["", "1001", "uid:", "uid:-1", "uid:abc", "user:1001", "**"] {
+            assert!(Principal::try_from(principal.to_string()).is_err(), "{principal}");
+        }
+    }
+
+    #[test]
+    fn call_implies_read() {
+        let policy = policy("call = [\"uid:1001\"]");
+
+        assert!(policy.can_call(&peer(1001, 1), &context("volume")));
+        assert!(policy.can_read(&peer(1001, 1), &context("volume")));
+        assert!(!policy.can_read(&peer(1002, 1), &context("volume")));
+    }
+
+    #[test]
+    fn read_doesnt_imply_call() {
+        let policy = policy("read = [\"gid:100\"]");
+
+        assert!(policy.can_read(&peer(1001, 100), &context("time")));
+        assert!(!policy.can_call(&peer(1001, 100), &context("time")));
+    }
+
+    #[test]
+    fn kind_rules_apply_to_the_instances() {
+        let policy = policy("[contexts.battery]\nread = [\"uid:1001\"]\n\n[contexts.\"battery@BAT1\"]\nread = [\"uid:1002\"]");
+
+        assert!(policy.can_read(&peer(1001, 1), &context("battery")));
+        assert!(policy.can_read(&peer(1001, 1), &context("battery@BAT1")));
+
+        assert!(policy.can_read(&peer(1002, 1), &context("battery@BAT1")));
+        assert!(!policy.can_read(&peer(1002, 1), &context("battery")));
+        assert!(!policy.can_read(&peer(1002, 1), &context("battery@BAT0")));
+        assert!(!policy.can_read(&peer(1001, 1), &context("volume")));
+    }
+
+    #[test]
+    fn instance_rule_grants_some_access() {
+        let policy = policy("[contexts.\"battery@BAT1\"]\nread = [\"uid:1002\"]");
+
+        assert!(policy.has_any_access(&peer(1002, 1)));
+        assert!(!policy.has_any_access(&peer(1001, 1)));
+        assert!(policy.allows_others());
+    }
+
+    #[test]
+    fn empty_policy_allows_the_owner_only() {
+        let policy = AccessPolicy::default();
+
+        assert!(!policy.allows_others());
+        assert!(!policy.has_any_access(&peer(1001, 1)));
+        assert!(policy.has_any_access(&Peer { is_owner: true, ..peer(1000, 1000) }));
+    }
+
+    #[test]
+    fn unknown_contexts_are_rejected() {
+        assert!(toml::from_str::<AccessPolicy>("[contexts.brigthness]\nread = [\"*\"]").is_err());
+    }
+}
@@ -1,21 +1,28 @@
-use std::{io::{Error, ErrorKind}, path::PathBuf, sync::Arc};
+use std::{io::ErrorKind, path::PathBuf, time::Duration};
 
 use async_trait::async_trait;
 use log::info;
 use regex::Regex;
-use tokio::{io::{AsyncReadExt, AsyncSeekExt}, sync::Mutex};
+use rsbar_protocol::{BatteryStatus, Call, ContextDescription, Event, EventDescription, EventKind, ValueDescription};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use crate::rsbar_context::{EventHandler, RsbarContext, RsbarContextContent};
+use crate::rsbar_context::{EventEmitter, RsbarContextContent, UpdateSchedule};
 
 const BATTERY_SYMLINK_PATH: &str = "/sys/class/power_supply/";
 const BATTERY_DIR_REGEX: &str = "^BAT[0-9]+$";
 
-pub enum BatteryStatus {
-    Charging,
-    Discharging,
-    Full,
-    NotCharging,
-    Unknown,
+const BATTERY_UPDATE_INTERVAL: u64 = 30000;
+
+// [contexts.battery] section of the daemon config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatterySettings {
+    pub enabled:     bool,
+    // Power supply name (e.g. "BAT1"), the last one matching BATTERY_DIR_REGEX is used if not set
+    battery:         Option<String>,
+    #[serde(deserialize_with = "crate::config::interval")]
+    update_interval: u64,
 }
 
 pub struct BatteryContext {
@@ -23,15 +30,19 @@ pub struct BatteryContext {
     status:         BatteryStatus,
     capacity_file:  Option<tokio::fs::File>,
     status_file:    Option<tokio::fs::File>,
-    event_handler:  Option<Arc<Mutex<EventHandler>>>,
+    settings:       BatterySettings,
+    event_emitter:  Option<EventEmitter>,
 }
 
 #[async_trait]
 impl RsbarContextContent for BatteryContext {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()> {
-        self.event_handler = Some(event_handler);
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()> {
+        self.event_emitter = Some(event_emitter);
 
-        let battery_dir = find_battery_dir().await?;
+        let battery_dir = match &self.settings.battery {
+            Some(battery) => PathBuf::from(BATTERY_SYMLINK_PATH).join(battery),
+            None          => find_battery_dir().await?,
+        };
 
         info!("Battery info dir: {}", battery_dir.to_string_lossy());
 
@@ -55,7 +66,7 @@ impl RsbarContextContent for BatteryContext {
     
         match read_file_content(self.capacity_file.as_mut().unwrap()).await?.parse::<u32>() {
             Ok(capacity) => self.capacity = capacity,
-            Err(err) => return Err(std::io::Error::new(ErrorKind::NotFound, format!("Bad capacity value: {}", err.to_string()))),
+            Err(err) => return Err(std::io::Error::new(ErrorKind::NotFound, format!("Bad capacity value: {err}"))),
         }
 
         let status_string = read_file_content(self.status_file.as_mut().unwrap()).await?;
@@ -66,62 +77,57 @@ impl RsbarContextContent for BatteryContext {
         Ok(())
     }
 
-    async fn call(&mut self, _procedure: &str, _args: &str) -> tokio::io::Result<()> {
-        Err(std::io::Error::new(ErrorKind::NotFound, "Battery context does not support calls"))
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::Interval(Duration::from_millis(self.settings.update_interval))
     }
 
-    async fn force_events(&mut self) -> tokio::io::Result<()> {
-        if self.event_handler.is_none() {
-            return Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found"));
-        }
-
-        let events = self.event_handler.as_mut().unwrap().lock().await;
+    fn describe(&self) -> ContextDescription {
+        let battery = self.settings.battery.as_deref().unwrap_or("the last BAT<N> power supply");
 
-        events.trigger_event("battery/capacity", &self.capacity.to_string()).await;
-        events.trigger_event("battery/status",   &self.status.to_string()).await;
-
-        Ok(())
+        ContextDescription::new(&format!("Charge of {battery}"), vec![
+            EventDescription { value: ValueDescription::integer(0, 100), ..EventDescription::new(EventKind::BatteryCapacity, "Capacity in percents") },
+            EventDescription { value: ValueDescription::enumeration(BatteryStatus::ALL), ..EventDescription::new(EventKind::BatteryStatus, "Charging status") },
+        ], Vec::new())
     }
-}
 
-impl std::fmt::Display for BatteryStatus {
-    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BatteryStatus::Charging    => write!(formatter, "Charging"),
-            BatteryStatus::Discharging => write!(formatter, "Discharging"),
-            BatteryStatus::Full        => write!(formatter, "Full"),
-            BatteryStatus::NotCharging => write!(formatter, "NotCharging"),
-            BatteryStatus::Unknown     => write!(formatter, "Unknown"),
-        }
+    async fn call(&mut self, _call: Call) -> tokio::io::Result<Option<serde_json::Value>> {
+        Err(std::io::Error::new(ErrorKind::Unsupported, "Battery context does not support calls"))
     }
-}
 
-impl std::str::FromStr for BatteryStatus {
-    type Err = Error;
-
-    fn from_str(string: &str) -> Result<Self, Self::Err> {
-        match string {
-            "Charging"     => Ok(BatteryStatus::Charging),
-            "Discharging"  => Ok(BatteryStatus::Discharging),
-            "Full"         => Ok(BatteryStatus::Full),
-            "Not charging" => Ok(BatteryStatus::NotCharging),
-            "Unknown"      => Ok(BatteryStatus::Unknown),
-            _ => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad status value: {string}"))),
+    async fn force_events(&mut self) -> tokio::io::Result<()> {
+        if self.event_emitter.is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "Event emitter was not found"));
         }
+
+        let events = self.event_emitter.as_ref().unwrap();
+
+        events.trigger_event(Event::BatteryCapacity(self.capacity));
+        events.trigger_event(Event::BatteryStatus(self.status));
+
+        Ok(())
     }
 }
 
 impl BatteryContext {
-    pub fn new() -> (String, RsbarContext) {
-        let new_context = Box::new(BatteryContext {
+    pub fn new(settings: BatterySettings) -> Self {
+        BatteryContext {
             capacity:       0,
             status:         BatteryStatus::Full,
             capacity_file:  None,
             status_file:    None,
-            event_handler:  None,
-        });
+            settings,
+            event_emitter:  None,
+        }
+    }
+}
 
-        ("battery".to_string(), RsbarContext::new(new_context))
+impl Default for BatterySettings {
+    fn default() -> Self {
+        BatterySettings {
+            enabled:         true,
+            battery:         None,
+            update_interval: BATTERY_UPDATE_INTERVAL,
+        }
     }
 }
 
@@ -166,6 +172,6 @@ async fn find_battery_dir() -> tokio::io::Result<std::path::PathBuf> {
 
     match battery_dir_path {
         Some(dir) => Ok(dir),
-        None      => Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found")),
+        None      => Err(std::io::Error::new(ErrorKind::NotFound, "Battery dir was not found")),
     }
 }
@@ -1,24 +1,38 @@
-use std::{io::ErrorKind, sync::Arc};
+use std::{io::ErrorKind, time::Duration};
 
 use async_trait::async_trait;
-use brightness::Brightness;
+use brightness::{Brightness, BrightnessDevice};
 use futures::TryStreamExt;
-use tokio::sync::Mutex;
+use rsbar_protocol::{Call, ContextDescription, Event, EventDescription, EventKind, ProcedureDescription, ValueDescription};
+use serde::Deserialize;
 
-use crate::rsbar_context::{EventHandler, RsbarContext, RsbarContextContent};
+use crate::rsbar_context::{EventEmitter, EventPolicy, RsbarContextContent, UpdateSchedule};
 
 const MAX_BRIGHTNESS: u32 = 100;
 const MIN_BRIGHTNESS: u32 = 0;
 
+// Dragging a brightness slider produces a call per step, so brightness events are sent at most once per this interval
+const BRIGHTNESS_EVENT_INTERVAL: u64 = 50;
+
+// [contexts.brightness] section of the daemon config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrightnessSettings {
+    pub enabled: bool,
+    // Backlight device name (e.g. "intel_backlight"), all of the devices are controlled if not set
+    device:      Option<String>,
+}
+
 pub struct BrightnessContext {
     brightness:    u32,
-    event_handler: Option<Arc<Mutex<EventHandler>>>,
+    settings:      BrightnessSettings,
+    event_emitter: Option<EventEmitter>,
 }
 
 #[async_trait]
 impl RsbarContextContent for BrightnessContext {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()> {
-        self.event_handler = Some(event_handler);
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()> {
+        self.event_emitter = Some(event_emitter);
 
         self.update().await?;
 
@@ -27,7 +41,7 @@ impl RsbarContextContent for BrightnessContext {
 
     async fn update(&mut self) -> tokio::io::Result<()> {
  
-        self.brightness = match get_brightness().await {
+        self.brightness = match get_brightness(self.settings.device.as_deref()).await {
             Ok(value) => value,
             Err(err) => return Err(std::io::Error::new(ErrorKind::NotFound, err)),
         };
@@ -37,77 +51,107 @@ impl RsbarContextContent for BrightnessContext {
         Ok(())
     }
 
-    async fn call(&mut self, procedure: &str, args: &str) -> tokio::io::Result<()> {
-        match procedure {
-            "setBrightness" => {
-                self.brightness = BrightnessContext::parse_brightness(args)?;
-                if let Err(err) = set_brightness(self.brightness).await {
-                    return Err(std::io::Error::new(ErrorKind::Other, format!("Unable to set the brightness value: {err}")));
+    // Brightness is changed through the calls
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::OnDemand
+    }
+
+    fn event_policy(&self, _kind: EventKind) -> EventPolicy {
+        EventPolicy::Throttle(Duration::from_millis(BRIGHTNESS_EVENT_INTERVAL))
+    }
+
+    fn describe(&self) -> ContextDescription {
+        let brightness_range = ValueDescription::integer(MIN_BRIGHTNESS.into(), MAX_BRIGHTNESS.into());
+        let devices = self.settings.device.as_deref().unwrap_or("all backlight devices");
+
+        ContextDescription::new(&format!("Brightness of {devices}"), vec![
+            EventDescription { value: brightness_range.clone(), ..EventDescription::new(EventKind::Brightness, "Brightness in percents") },
+        ], vec![
+            ProcedureDescription::new("setBrightness", Some(brightness_range), "Sets the brightness in percents"),
+        ])
+    }
+
+    async fn call(&mut self, call: Call) -> tokio::io::Result<Option<serde_json::Value>> {
+        match call {
+            Call::SetBrightness(brightness) => {
+                BrightnessContext::check_brightness(brightness)?;
+                if let Err(err) = set_brightness(self.settings.device.as_deref(), brightness).await {
+                    return Err(std::io::Error::other(format!("Unable to set the brightness value: {err}")));
                 }
+
+                self.brightness = brightness;
             },
-            _ => return Err(std::io::Error::new(ErrorKind::NotFound, format!("Bad procedure value for brightness context: {procedure}"))),
+            _ => return Err(std::io::Error::new(ErrorKind::Unsupported, format!("Bad procedure value for brightness context: {}", call.procedure()))),
         };
 
         self.force_events().await?;
 
-        Ok(())
+        Ok(None)
     }
 
     async fn force_events(&mut self) -> tokio::io::Result<()> {
-        if self.event_handler.is_none() {
-            return Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found"));
+        if self.event_emitter.is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "Event emitter was not found"));
         }
 
-        self.event_handler.as_mut().unwrap().lock().await
-            .trigger_event("brightness/brightness", &self.brightness.to_string()).await;
+        self.event_emitter.as_ref().unwrap().trigger_event(Event::Brightness(self.brightness));
 
         Ok(())
     }
 }
 
 impl BrightnessContext {
-    pub fn new() -> (String, RsbarContext) {
-        let new_context = Box::new(BrightnessContext {
+    pub fn new(settings: BrightnessSettings) -> Self {
+        BrightnessContext {
             brightness:    0,
-            event_handler: None,
-        });
-
-        ("brightness".to_string(), RsbarContext::new(new_context))
+            settings,
+            event_emitter: None,
+        }
     }
 
-    fn parse_brightness(args: &str) -> tokio::io::Result<u32> {
-
-        let parse_result = args.parse::<u32>();
-        
-        if !parse_result.is_ok() {
-            return Err(std::io::Error::new(ErrorKind::Other, format!("Bad brightness value: {args}")));
-        }
-        
-        let value = parse_result.unwrap();
-        
-        if value < MIN_BRIGHTNESS || value > MAX_BRIGHTNESS {
-            return Err(std::io::Error::new(ErrorKind::Other, format!("Brightness value is out of range: {args}")));
+    fn check_brightness(value: u32) -> tokio::io::Result<()> {
+        if !(MIN_BRIGHTNESS..=MAX_BRIGHTNESS).contains(&value) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Brightness value is out of range: {value}")));
         }
 
-        Ok(value)
+        Ok(())
     }
 }
 
- async fn get_brightness() -> Result<u32, brightness::Error> {
-    let brightness_device = brightness::brightness_devices().try_next().await?;
+impl Default for BrightnessSettings {
+    fn default() -> Self {
+        BrightnessSettings {
+            enabled: true,
+            device:  None,
+        }
+    }
+}
 
-    match brightness_device {
+async fn get_brightness(device_name: Option<&str>) -> Result<u32, brightness::Error> {
+    match find_devices(device_name).await?.first() {
         Some(device) => Ok(device.get().await?),
         None => Err(brightness::Error::ListingDevicesFailed(Box::new(std::io::Error::new(ErrorKind::NotFound, "Brightness device not found")))),
     }
 }
 
-async fn set_brightness(value: u32) -> Result<(), brightness::Error> {
-
-    brightness::brightness_devices().try_for_each(|mut device| async move {
+async fn set_brightness(device_name: Option<&str>, value: u32) -> Result<(), brightness::Error> {
+    for mut device in find_devices(device_name).await? {
         device.set(value).await?;
+    }
 
-        Ok(())
-    }).await
+    Ok(())
 }
 
+// Devices with the given name (or all of them)
+async fn find_devices(device_name: Option<&str>) -> Result<Vec<BrightnessDevice>, brightness::Error> {
+    let mut devices = Vec::new();
+    let mut device_stream = brightness::brightness_devices();
+
+    while let Some(device) = device_stream.try_next().await? {
+        if device_name.is_none() || device_name == Some(device.device_name().await?.as_str()) {
+            devices.push(device);
+        }
+    }
+
+    Ok(devices)
+}
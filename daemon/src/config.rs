@@ -0,0 +1,203 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::{Path, PathBuf}};
+
+use rsbar_protocol::{take_flag, ContextId, ContextKind};
+use serde::{de::{self, MapAccess, Visitor}, Deserialize, Deserializer};
+
+use crate::{access::AccessPolicy, event_queue::SubscriberSettings, history::HistorySettings, battery_context::{BatteryContext, BatterySettings}, brightness_context::{BrightnessContext, BrightnessSettings}, hyprland_context::{HyprlandContext, HyprlandSettings}};
+use crate::{rsbar_context::RsbarContextContent, time_context::{TimeContext, TimeSettings}, volume_context::{VolumeContext, VolumeSettings}};
+
+// Daemon config is read from "$XDG_CONFIG_HOME/rsbar/daemon.toml" or from the file passed with the --config flag.
+// Missing default config means the default settings
+const CONFIG_FLAG: &str = "--config";
+
+// Location of the config, it's read again on reload
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    path:        Option<PathBuf>,
+    is_explicit: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub access:      AccessPolicy,
+    pub subscribers: SubscriberSettings,
+    pub history:     HistorySettings,
+    pub contexts:    ContextsConfig,
+}
+
+// [contexts.<context id>] sections. Each section configures a context instance:
+// "[contexts.battery]" is the default battery context and "[contexts."battery@BAT1"]" is an additional one.
+// Default instances of all of the context kinds are running unless they are disabled with "enabled = false"
+#[derive(Debug, Clone)]
+pub struct ContextsConfig(BTreeMap<ContextId, ContextSettings>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextSettings {
+    Time(TimeSettings),
+    Volume(VolumeSettings),
+    Brightness(BrightnessSettings),
+    Hyprland(HyprlandSettings),
+    Battery(BatterySettings),
+}
+
+impl ConfigSource {
+    pub fn from_args(args: &mut Vec<String>) -> std::io::Result<Self> {
+        match take_flag(args, CONFIG_FLAG)? {
+            Some(path) => Ok(ConfigSource { path: Some(PathBuf::from(path)), is_explicit: true }),
+            None       => Ok(ConfigSource { path: default_path(), is_explicit: false }),
+        }
+    }
+
+    pub fn load(&self) -> std::io::Result<DaemonConfig> {
+        let Some(path) = &self.path else {
+            return Ok(DaemonConfig::default());
+        };
+
+        match DaemonConfig::load(path) {
+            Err(error) if error.kind() == ErrorKind::NotFound && !self.is_explicit => Ok(DaemonConfig::default()),
+            result => result,
+        }
+    }
+}
+
+impl DaemonConfig {
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|error| std::io::Error::new(error.kind(), format!("Unable to read config {}: {error}", path.display())))?;
+
+        toml::from_str(&content)
+            .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, format!("Bad config {}: {error}", path.display())))
+    }
+}
+
+impl ContextsConfig {
+    pub fn enabled(self) -> impl Iterator<Item = (ContextId, ContextSettings)> {
+        self.0.into_iter().filter(|(_, settings)| settings.is_enabled())
+    }
+}
+
+impl Default for ContextsConfig {
+    fn default() -> Self {
+        let contexts = ContextKind::ALL.iter()
+            .filter_map(|kind| Some((ContextId::from(*kind), ContextSettings::default_for(*kind)?)))
+            .collect();
+
+        ContextsConfig(contexts)
+    }
+}
+
+// NOTE settings type depends on the section name, so the sections are deserialized one by one.
+// That way the errors (e.g. unknown keys) keep their positions in the file
+impl<'de> Deserialize<'de> for ContextsConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(ContextsVisitor)
+    }
+}
+
+struct ContextsVisitor;
+
+impl<'de> Visitor<'de> for ContextsVisitor {
+    type Value = ContextsConfig;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a table of context sections")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut contexts = ContextsConfig::default();
+
+        // NOTE section name is parsed as a key, so an unknown context is reported at its own section
+        while let Some(context) = map.next_key::<ContextId>()? {
+            let settings = match context.kind() {
+                ContextKind::Time       => ContextSettings::Time(map.next_value()?),
+                ContextKind::Volume     => ContextSettings::Volume(map.next_value()?),
+                ContextKind::Brightness => ContextSettings::Brightness(map.next_value()?),
+                ContextKind::Hyprland   => ContextSettings::Hyprland(map.next_value()?),
+                ContextKind::Battery    => ContextSettings::Battery(map.next_value()?),
+                ContextKind::Rsbar      => return Err(de::Error::custom("Builtin rsbar context can't be configured")),
+            };
+
+            contexts.0.insert(context, settings);
+        }
+
+        Ok(contexts)
+    }
+}
+
+impl ContextSettings {
+    fn default_for(kind: ContextKind) -> Option<Self> {
+        match kind {
+            ContextKind::Time       => Some(ContextSettings::Time(TimeSettings::default())),
+            ContextKind::Volume     => Some(ContextSettings::Volume(VolumeSettings::default())),
+            ContextKind::Brightness => Some(ContextSettings::Brightness(BrightnessSettings::default())),
+            ContextKind::Hyprland   => Some(ContextSettings::Hyprland(HyprlandSettings::default())),
+            ContextKind::Battery    => Some(ContextSettings::Battery(BatterySettings::default())),
+            ContextKind::Rsbar      => None,
+        }
+    }
+
+    pub fn into_context(self) -> Box<dyn RsbarContextContent + Send + Sync> {
+        match self {
+            ContextSettings::Time(settings)       => Box::new(TimeContext::new(settings)),
+            ContextSettings::Volume(settings)     => Box::new(VolumeContext::new(settings)),
+            ContextSettings::Brightness(settings) => Box::new(BrightnessContext::new(settings)),
+            ContextSettings::Hyprland(settings)   => Box::new(HyprlandContext::new(settings)),
+            ContextSettings::Battery(settings)    => Box::new(BatteryContext::new(settings)),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        match self {
+            ContextSettings::Time(settings)       => settings.enabled,
+            ContextSettings::Volume(settings)     => settings.enabled,
+            ContextSettings::Brightness(settings) => settings.enabled,
+            ContextSettings::Hyprland(settings)   => settings.enabled,
+            ContextSettings::Battery(settings)    => settings.enabled,
+        }
+    }
+}
+
+// Intervals are set in milliseconds and can't be zero
+pub fn interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match u64::deserialize(deserializer)? {
+        0        => Err(de::Error::custom("Interval must be greater than zero")),
+        interval => Ok(interval),
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok()?;
+
+    Some(config_dir.join("rsbar").join("daemon.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_context_is_reported_at_its_section() {
+        let error = toml::from_str::<DaemonConfig>("[access]\nread = []\n\n[contexts.foo]\nenabled = true\n").unwrap_err();
+
+        assert!(error.to_string().contains("line 4"), "{error}");
+        assert!(error.to_string().contains("Unknown context: foo"), "{error}");
+    }
+
+    #[test]
+    fn bad_time_format_is_reported_at_its_line() {
+        let error = toml::from_str::<DaemonConfig>("[contexts.time]\nenabled = true\nformat = \"%H:%Q\"\n").unwrap_err();
+
+        assert!(error.to_string().contains("line 3"), "{error}");
+        assert!(error.to_string().contains("Bad time format"), "{error}");
+    }
+
+    #[test]
+    fn unknown_key_is_reported_at_its_line() {
+        let error = toml::from_str::<DaemonConfig>("[contexts.time]\nenabled = true\n\n[contexts.volume]\nenabled = true\nstep = 5\n").unwrap_err();
+
+        assert!(error.to_string().contains("line 6"), "{error}");
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicU64, Ordering}, Mutex}, time::Duration};
+
+use rsbar_protocol::{Backpressure, ContextEvent};
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+const QUEUE_SIZE:    usize = 256;
+const WRITE_TIMEOUT: u64   = 5000;
+
+// [subscribers] section of the daemon config. Clients may choose another policy for their own connection
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubscriberSettings {
+    backpressure: Backpressure,
+    #[serde(deserialize_with = "queue_size")]
+    queue_size:   usize,
+    // Used by the "disconnect" policy
+    #[serde(deserialize_with = "crate::config::interval")]
+    timeout:      u64,
+}
+
+// Outgoing events of a single connection. Events are queued by the connection task and written to the socket
+// by a separate task, so a client which doesn't read its events never delays the other clients
+pub struct EventQueue {
+    state:    Mutex<QueueState>,
+    ready:    Notify,
+    settings: SubscriberSettings,
+    dropped:  AtomicU64,
+}
+
+struct QueueState {
+    events:       VecDeque<ContextEvent>,
+    backpressure: Backpressure,
+    is_closed:    bool,
+}
+
+impl EventQueue {
+    pub fn new(settings: SubscriberSettings) -> Self {
+        EventQueue {
+            state: Mutex::new(QueueState {
+                events:       VecDeque::new(),
+                backpressure: settings.backpressure,
+                is_closed:    false,
+            }),
+            ready:   Notify::new(),
+            dropped: AtomicU64::new(0),
+            settings,
+        }
+    }
+
+    pub fn backpressure(&self) -> Backpressure {
+        self.state.lock().unwrap().backpressure
+    }
+
+    pub fn set_backpressure(&self, backpressure: Backpressure) {
+        self.state.lock().unwrap().backpressure = backpressure;
+    }
+
+    // Events which were dropped or coalesced
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn add_dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    // Writing an event may take this long before the client is disconnected
+    pub fn write_timeout(&self) -> Option<Duration> {
+        match self.backpressure() {
+            Backpressure::Disconnect => Some(Duration::from_millis(self.settings.timeout)),
+            _                        => None,
+        }
+    }
+
+    // Returns false if the client has to be disconnected
+    pub fn push(&self, mut event: ContextEvent) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        match state.backpressure {
+            Backpressure::Coalesce => {
+                // NOTE coalesced event keeps its place in the queue, so the other events aren't delayed by it
+                let address = event.address();
+
+                match state.events.iter_mut().find(|queued| queued.address() == address) {
+                    Some(queued) => {
+                        // NOTE replaced values are counted in the stamp, so the client doesn't take the skipped sequence numbers for a loss
+                        if let (Some(queued_stamp), Some(stamp)) = (queued.stamp, &mut event.stamp) {
+                            stamp.coalesced = queued_stamp.coalesced + stamp.seq.saturating_sub(queued_stamp.seq);
+                        }
+
+                        *queued = event;
+                        self.add_dropped(1);
+                    },
+                    None => state.events.push_back(event),
+                }
+            },
+            Backpressure::DropOldest => {
+                if state.events.len() >= self.settings.queue_size {
+                    state.events.pop_front();
+                    self.add_dropped(1);
+                }
+
+                state.events.push_back(event);
+            },
+            Backpressure::Disconnect => {
+                if state.events.len() >= self.settings.queue_size {
+                    self.add_dropped(1);
+                    return false;
+                }
+
+                state.events.push_back(event);
+            },
+        }
+
+        self.ready.notify_one();
+
+        true
+    }
+
+    // Queues the last event regardless of the policy, the queue is closed once it's written
+    pub fn finish(&self, event: ContextEvent) {
+        let mut state = self.state.lock().unwrap();
+
+        state.events.push_back(event);
+        state.is_closed = true;
+
+        self.ready.notify_one();
+    }
+
+    // Returns None once the queue is finished and all of its events are taken
+    pub async fn pop(&self) -> Option<ContextEvent> {
+        loop {
+            // NOTE notification is created before the queue is checked, so a push in between isn't missed
+            let ready = self.ready.notified();
+
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if let Some(event) = state.events.pop_front() {
+                    return Some(event);
+                }
+
+                if state.is_closed {
+                    return None;
+                }
+            }
+
+            ready.await;
+        }
+    }
+}
+
+impl Default for SubscriberSettings {
+    fn default() -> Self {
+        SubscriberSettings {
+            backpressure: Backpressure::default(),
+            queue_size:   QUEUE_SIZE,
+            timeout:      WRITE_TIMEOUT,
+        }
+    }
+}
+
+fn queue_size<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0    => Err(serde::de::Error::custom("Queue size must be greater than zero")),
+        size => Ok(size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsbar_protocol::{Event, EventStamp};
+
+    use super::*;
+
+    fn volume(seq: u64) -> ContextEvent {
+        ContextEvent { stamp: Some(EventStamp { seq, timestamp: 0, coalesced: 0 }), ..Event::Volume(seq as u32).into() }
+    }
+
+    #[tokio::test]
+    async fn coalesced_values_are_counted_in_the_stamp() {
+        let queue = EventQueue::new(SubscriberSettings::default());
+
+        for seq in 5..=8 {
+            assert!(queue.push(volume(seq)));
+        }
+
+        let stamp = queue.pop().await.unwrap().stamp.unwrap();
+
+        assert_eq!((stamp.seq, stamp.coalesced), (8, 3));
+        assert_eq!(queue.dropped(), 3);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, io::ErrorKind, sync::{Mutex, RwLock}};
+
+use rsbar_protocol::{ContextEvent, EventAddress, HistoryQuery, HistorySample, ValueType};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::rsbar_context::timestamp;
+
+const MAX_SAMPLES: usize = 1024;
+
+// [history] section of the daemon config. Recorded events are listed in [history.events] with their retention
+// in milliseconds, e.g. "battery/capacity" = 3600000. Only integer events can be recorded
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistorySettings {
+    // Upper bound of the samples kept for each event, applied on top of the retention
+    #[serde(deserialize_with = "max_samples")]
+    max_samples: usize,
+    #[serde(deserialize_with = "recorded_events")]
+    events:      BTreeMap<EventAddress, u64>,
+}
+
+// Ring buffers of the recent values of the recorded events
+pub struct EventHistory {
+    settings: RwLock<HistorySettings>,
+    series:   Mutex<HashMap<EventAddress, VecDeque<HistorySample>>>,
+}
+
+impl EventHistory {
+    pub fn new() -> Self {
+        EventHistory {
+            settings: RwLock::new(HistorySettings::default()),
+            series:   Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Values of the events which are no longer recorded are dropped
+    pub fn set_settings(&self, settings: HistorySettings) {
+        self.series.lock().unwrap().retain(|address, _| settings.events.contains_key(address));
+
+        *self.settings.write().unwrap() = settings;
+    }
+
+    // NOTE only stamped events are recorded, the stamp time is the sample time
+    pub fn record(&self, event: &ContextEvent) {
+        let settings = self.settings.read().unwrap();
+        let address  = event.address();
+
+        let (Some(retention), Some(stamp), Some(value)) = (settings.events.get(&address), event.stamp, event.event.value_to_json().as_i64()) else {
+            return;
+        };
+
+        let mut series = self.series.lock().unwrap();
+        let samples = series.entry(address).or_default();
+
+        samples.push_back(HistorySample { timestamp: stamp.timestamp, value });
+
+        while samples.len() > settings.max_samples || samples.front().is_some_and(|sample| sample.timestamp.saturating_add(*retention) < stamp.timestamp) {
+            samples.pop_front();
+        }
+    }
+
+    pub fn query(&self, query: &HistoryQuery) -> std::io::Result<Vec<HistorySample>> {
+        let Some(retention) = self.settings.read().unwrap().events.get(&query.event).copied() else {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("History of {} isn't recorded", query.event)));
+        };
+
+        if query.step == Some(0) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, "Step must be greater than zero"));
+        }
+
+        // NOTE samples of a quiet event are dropped only when the next one is recorded, so retention is applied here too
+        let now  = timestamp();
+        let from = [query.from, query.last.map(|last| now.saturating_sub(last)), Some(now.saturating_sub(retention))].into_iter().flatten().max().unwrap_or(0);
+        let to   = query.to.unwrap_or(u64::MAX);
+
+        let samples: Vec<HistorySample> = self.series.lock().unwrap().get(&query.event).into_iter().flatten()
+            .filter(|sample| (from..=to).contains(&sample.timestamp))
+            .copied()
+            .collect();
+
+        match query.step {
+            Some(step) => Ok(downsample(&samples, from, step)),
+            None       => Ok(samples),
+        }
+    }
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        HistorySettings {
+            max_samples: MAX_SAMPLES,
+            events:      BTreeMap::new(),
+        }
+    }
+}
+
+// Averages the samples of each step long interval, intervals without samples are skipped
+fn downsample(samples: &[HistorySample], from: u64, step: u64) -> Vec<HistorySample> {
+    let mut intervals: Vec<(u64, i64, i64)> = Vec::new();
+
+    for sample in samples {
+        let start = from + (sample.timestamp - from) / step * step;
+
+        match intervals.last_mut() {
+            Some((last_start, sum, count)) if *last_start == start => {
+                *sum   += sample.value;
+                *count += 1;
+            },
+            _ => intervals.push((start, sample.value, 1)),
+        }
+    }
+
+    intervals.into_iter()
+        .map(|(timestamp, sum, count)| HistorySample { timestamp, value: (sum as f64 / count as f64).round() as i64 })
+        .collect()
+}
+
+fn max_samples<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0     => Err(de::Error::custom("Max samples count must be greater than zero")),
+        count => Ok(count),
+    }
+}
+
+fn recorded_events<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<EventAddress, u64>, D::Error> {
+    let events = BTreeMap::<EventAddress, u64>::deserialize(deserializer)?;
+
+    for (address, retention) in &events {
+        if address.kind.value_description().value_type != ValueType::Integer {
+            return Err(de::Error::custom(format!("History of {address} can't be recorded: only integer events are supported")));
+        }
+
+        if *retention == 0 {
+            return Err(de::Error::custom(format!("Retention of {address} must be greater than zero")));
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use rsbar_protocol::{ContextKind, Event, EventKind, EventStamp};
+
+    use super::*;
+
+    fn history(settings: &str) -> EventHistory {
+        let history = EventHistory::new();
+        history.set_settings(toml::from_str(settings).unwrap());
+        history
+    }
+
+    fn capacity(value: u32, timestamp: u64) -> ContextEvent {
+        ContextEvent {
+            context: ContextKind::Battery.into(),
+            event:   Event::BatteryCapacity(value),
+            stamp:   Some(EventStamp { seq: 0, timestamp, coalesced: 0 }),
+        }
+    }
+
+    fn query(last: Option<u64>, step: Option<u64>) -> HistoryQuery {
+        HistoryQuery { event: "battery/capacity".parse().unwrap(), from: None, to: None, last, step }
+    }
+
+    fn sample(timestamp: u64, value: i64) -> HistorySample {
+        HistorySample { timestamp, value }
+    }
+
+    #[test]
+    fn samples_are_averaged_per_step() {
+        let samples = [sample(100, 10), sample(101, 21), sample(104, 30), sample(110, 50), sample(121, 70)];
+
+        assert_eq!(downsample(&samples, 100, 5), vec![sample(100, 20), sample(110, 50), sample(120, 70)]);
+        assert_eq!(downsample(&samples, 100, 100), vec![sample(100, 36)]);
+        assert_eq!(downsample(&[], 100, 5), vec![]);
+    }
+
+    #[test]
+    fn samples_older_than_the_retention_are_not_returned() {
+        let history = history("[events]\n\"battery/capacity\" = 60000\n");
+        let now     = timestamp();
+
+        history.record(&capacity(90, now - 120_000));
+        history.record(&capacity(80, now - 100_000));
+
+        // NOTE quiet event: nothing has dropped these samples yet
+        assert_eq!(history.query(&query(None, None)).unwrap(), vec![]);
+
+        history.record(&capacity(70, now - 30_000));
+        history.record(&capacity(60, now - 1_000));
+
+        assert_eq!(history.query(&query(None, None)).unwrap(), vec![sample(now - 30_000, 70), sample(now - 1_000, 60)]);
+        assert_eq!(history.query(&query(Some(10_000), None)).unwrap(), vec![sample(now - 1_000, 60)]);
+    }
+
+    #[test]
+    fn max_samples_is_applied_on_top_of_the_retention() {
+        let history = history("max_samples = 2\n\n[events]\n\"battery/capacity\" = 60000\n");
+        let now     = timestamp();
+
+        for (index, value) in [90, 80, 70].into_iter().enumerate() {
+            history.record(&capacity(value, now - 3_000 + index as u64 * 1_000));
+        }
+
+        assert_eq!(history.query(&query(None, None)).unwrap(), vec![sample(now - 2_000, 80), sample(now - 1_000, 70)]);
+    }
+
+    #[test]
+    fn unrecorded_events_and_zero_step_are_rejected() {
+        let history = history("[events]\n\"battery/capacity\" = 60000\n");
+        let volume  = HistoryQuery { event: EventAddress { context: ContextKind::Volume.into(), kind: EventKind::Volume }, ..query(None, None) };
+
+        assert_eq!(history.query(&volume).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(history.query(&query(None, Some(0))).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -2,12 +2,13 @@ use core::str;
 use std::{env, io::ErrorKind, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use log::{error, info, warn};
+use log::{info, warn};
 use once_cell::sync::Lazy;
+use rsbar_protocol::{Call, ContextDescription, Event, EventDescription, EventKind, ProcedureDescription, ValueDescription};
 use serde::{Deserialize, Serialize};
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::UnixStream, sync::Mutex, time::interval};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::UnixStream, sync::Mutex, task::JoinHandle, time::interval};
 
-use crate::rsbar_context::{EventHandler, RsbarContext, RsbarContextContent};
+use crate::rsbar_context::{EventEmitter, RsbarContextContent, UpdateSchedule};
 
 //--------------------------------------------------------------------------------------------------------------------------------
 //---------------------------------------------------------[ Globals ]------------------------------------------------------------
@@ -28,9 +29,20 @@ static EVENT_SOCKET: Lazy<String> = Lazy::new(||
 //----------------------------------------------------------[ Context ]-----------------------------------------------------------
 //--------------------------------------------------------------------------------------------------------------------------------
 
+// [contexts.hyprland] section of the daemon config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HyprlandSettings {
+    pub enabled:           bool,
+    #[serde(deserialize_with = "crate::config::interval")]
+    reconnection_interval: u64,
+}
+
 pub struct HyprlandContext {
     current_workspace: Arc<Mutex<i32>>,
-    event_handler:     Option<Arc<Mutex<EventHandler>>>,
+    settings:          HyprlandSettings,
+    event_emitter:     Option<EventEmitter>,
+    listener:          Option<JoinHandle<tokio::io::Result<()>>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -55,61 +67,94 @@ struct Workspace {
 
 #[async_trait]
 impl RsbarContextContent for HyprlandContext {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()>{
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()>{
 
-        init_lazy_cells();
+        init_lazy_cells()?;
+
+        // NOTE context is initialized again after a restart, so the previous listener is stopped
+        if let Some(listener) = self.listener.take() {
+            listener.abort();
+        }
 
-        self.event_handler = Some(event_handler.clone());
-        tokio::spawn(Self::listener_loop(event_handler, self.current_workspace.clone()));
+        self.event_emitter = Some(event_emitter.clone());
+        self.listener = Some(tokio::spawn(Self::hyprland_event_listener_async(event_emitter, self.current_workspace.clone(), self.settings.reconnection_interval)));
 
         Ok(())
     }
 
+    // Listener runs until an error, which is reported here, so the supervisor restarts the context
     async fn update(&mut self) -> tokio::io::Result<()> {
-        Ok(())
+        if !self.listener.as_ref().is_some_and(|listener| listener.is_finished()) {
+            return Ok(());
+        }
+
+        match self.listener.take().unwrap().await {
+            Ok(Ok(()))     => Err(std::io::Error::other("Hyprland listener has stopped")),
+            Ok(Err(error)) => Err(error),
+            Err(error)     => Err(std::io::Error::other(format!("Hyprland listener has failed: {error}"))),
+        }
     }
 
-    async fn call(&mut self, procedure: &str, args: &str) -> tokio::io::Result<()> {
-        match procedure {
-            "setWorkspace" => { 
-                Self::make_hyprctl_request(&format!("dispatch workspace {}", args)).await?; 
+    // Workspace events are triggered by the hyprland socket listener, updates only check that it's still running
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::Interval(Duration::from_millis(self.settings.reconnection_interval))
+    }
+
+    fn describe(&self) -> ContextDescription {
+        ContextDescription::new("Hyprland workspaces", vec![
+            EventDescription::new(EventKind::HyprlandWorkspace, "Active workspace id (-1 if it's unknown)"),
+        ], vec![
+            ProcedureDescription::new("setWorkspace", Some(ValueDescription::of::<i32>()), "Switches to the workspace with the given id"),
+        ])
+    }
+
+    async fn call(&mut self, call: Call) -> tokio::io::Result<Option<serde_json::Value>> {
+        match call {
+            Call::SetWorkspace(workspace) => { 
+                let response = Self::make_hyprctl_request(&format!("dispatch workspace {}", workspace)).await?; 
+
+                if response.trim() != "ok" {
+                    return Err(std::io::Error::other(format!("Hyprland rejected workspace change: {}", response.trim())));
+                }
             },
-            _ => return Err(std::io::Error::new(ErrorKind::NotFound, format!("Bad procedure value for hyprland context: {procedure}"))),
+            _ => return Err(std::io::Error::new(ErrorKind::Unsupported, format!("Bad procedure value for hyprland context: {}", call.procedure()))),
         };
 
-        Ok(())
+        Ok(None)
     }
 
     async fn force_events(&mut self) -> tokio::io::Result<()> {
-        if self.event_handler.is_none() {
-            return Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found"));
+        if self.event_emitter.is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "Event emitter was not found"));
         }
 
-        self.event_handler.as_mut().unwrap().lock().await
-            .trigger_event("hyprland/workspace", &self.current_workspace.lock().await.to_string()).await;
+        self.event_emitter.as_ref().unwrap().trigger_event(Event::HyprlandWorkspace(*self.current_workspace.lock().await));
 
         Ok(())
     }
 }
 
-impl HyprlandContext {
-    pub fn new() -> (String, RsbarContext) {
-        let new_context = Box::new(HyprlandContext { 
-            current_workspace: Arc::new(Mutex::new(-1)),
-            event_handler:     None,
-        });
-
-        ("hyprland".to_string(), RsbarContext::new(new_context))
+// Listener isn't needed once the context is stopped (e.g. on reload)
+impl Drop for HyprlandContext {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            listener.abort();
+        }
     }
+}
 
-    async fn listener_loop(event_handler: Arc<Mutex<EventHandler>>, current_workspace: Arc<Mutex<i32>>) {
-        if let Err(result) = Self::hyprland_event_listener_async(&event_handler, &current_workspace).await {
-            error!("Hyprland error: {}", result);
+impl HyprlandContext {
+    pub fn new(settings: HyprlandSettings) -> Self {
+        HyprlandContext { 
+            current_workspace: Arc::new(Mutex::new(-1)),
+            settings,
+            event_emitter:     None,
+            listener:          None,
         }
     }
 
-    async fn hyprland_event_listener_async(event_handler: &Arc<Mutex<EventHandler>>, current_workspace: &Arc<Mutex<i32>>) -> tokio::io::Result<()> {
-        let mut interval = interval(Duration::from_millis(RECONNECTION_INTERVAL));
+    async fn hyprland_event_listener_async(event_emitter: EventEmitter, current_workspace: Arc<Mutex<i32>>, reconnection_interval: u64) -> tokio::io::Result<()> {
+        let mut interval = interval(Duration::from_millis(reconnection_interval));
 
         info!("Connecting to the hyprland socket");
 
@@ -148,14 +193,14 @@ impl HyprlandContext {
         }
 
         *current_workspace.lock().await = workspace;
-        event_handler.lock().await.trigger_event("hyprland/workspace", &workspace.to_string()).await;
+        event_emitter.trigger_event(Event::HyprlandWorkspace(workspace));
 
         loop {
             let bytes_count = stream.read(&mut buffer).await?;
-            
-            // TODO check if response is valid
+
+            // NOTE hyprland has closed the socket (e.g. it's restarting)
             if bytes_count == 0 {
-                continue;
+                return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Hyprland socket is closed"));
             }
         
             let response = String::from_utf8_lossy(&buffer[..bytes_count]);
@@ -167,7 +212,7 @@ impl HyprlandContext {
                     // TODO function
                     let workspace = Self::get_active_workspace_async().await?;
                     *current_workspace.lock().await = workspace;
-                    event_handler.lock().await.trigger_event("hyprland/workspace", &workspace.to_string()).await;
+                    event_emitter.trigger_event(Event::HyprlandWorkspace(workspace));
                     
                     break;
                 }
@@ -187,14 +232,22 @@ impl HyprlandContext {
     async fn make_hyprctl_request(request: &String) -> tokio::io::Result<String> {
         let mut stream = UnixStream::connect(hyprctl_socket()).await?;
     
-        let _ = stream.write_all(request.as_bytes()).await?;
+        stream.write_all(request.as_bytes()).await?;
     
         let mut buf = [0; 8192]; //NOTE buffer size is taken from hyprctl sources
         let bytes_count = stream.read(&mut buf).await?;
         
-        let response = String::from_utf8(buf[..bytes_count].to_vec()).unwrap();
-    
-        Ok(response)
+        String::from_utf8(buf[..bytes_count].to_vec())
+            .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, format!("Hyprland response isn't valid UTF-8: {error}")))
+    }
+}
+
+impl Default for HyprlandSettings {
+    fn default() -> Self {
+        HyprlandSettings {
+            enabled:               true,
+            reconnection_interval: RECONNECTION_INTERVAL,
+        }
     }
 }
 
@@ -202,12 +255,21 @@ impl HyprlandContext {
 //---------------------------------------------------[ Lazy cells ]-------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------------------------
 //
-fn init_lazy_cells() {
+// Hyprland is not running if its environment variables are not set
+fn init_lazy_cells() -> tokio::io::Result<()> {
+    for variable in ["XDG_RUNTIME_DIR", "HYPRLAND_INSTANCE_SIGNATURE"] {
+        if env::var(variable).is_err() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, format!("{variable} environment variable is not set")));
+        }
+    }
+
     Lazy::force(&XDG_RUNTIME_DIR);
     Lazy::force(&HYPRLAND_INSTANCE_SIGNATURE);
 
     Lazy::force(&HYPRCTL_SOCKET);
     Lazy::force(&EVENT_SOCKET);
+
+    Ok(())
 }
 
 macro_rules! get_lazy {
@@ -1,3 +1,7 @@
+mod access;
+mod config;
+mod event_queue;
+mod history;
 mod server_context;
 mod volume_context;
 mod brightness_context;
@@ -5,151 +9,468 @@ mod hyprland_context;
 mod time_context;
 mod rsbar_context;
 mod battery_context;
-
-use battery_context::BatteryContext;
-use brightness_context::BrightnessContext;
-use hyprland_context::HyprlandContext;
+mod recording;
+mod supervisor;
+mod systemd;
+
+use access::Peer;
+use config::ConfigSource;
+use event_queue::EventQueue;
+use rsbar_protocol::{CallReply, ContextEvent, Event, ProtocolVersion, SocketPaths, SubscriptionRequest, FRAME_DELIMITER};
+use recording::Session;
 use server_context::ServerContext;
 
-use time_context::TimeContext;
-use tokio::net::{UnixStream, UnixListener};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
-use tokio::{sync::Mutex, task, time};
-use volume_context::VolumeContext;
-use tokio::sync::mpsc;
-use std::{sync::Arc, time::Duration};
+use tokio::net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixStream, UnixListener};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tokio::task::{self, JoinHandle};
+use std::{fs::{DirBuilder, File, OpenOptions, TryLockError}, io::{ErrorKind, Write}, os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt}, path::PathBuf, sync::Arc, time::Duration};
 use log::{error, warn, info};
 
-const POLLING_INTERVAL: u64 = 1000; 
+// Time given to the clients to receive the pending events on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
     colog::init();
 
-    let mut main_context = ServerContext::new();
+    let mut args: Vec<String> = std::env::args().collect();
+    let paths = SocketPaths::from_args(&mut args)?;
+
+    let config_source = ConfigSource::from_args(&mut args)?;
+
+    let session = match Session::from_args(&mut args) {
+        Ok(session) => session,
+        Err(error)  => {
+            error!("Unable to start the session: {error}");
+            return Err(error);
+        },
+    };
+
+    let config = match config_source.load() {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Unable to load the daemon config: {error}");
+            return Err(error);
+        },
+    };
+
+    if args.len() > 1 {
+        warn!("Unknown arguments: {}", args[1..].join(" "));
+    }
 
-    main_context.add_context(VolumeContext::new());
-    main_context.add_context(BrightnessContext::new());
-    main_context.add_context(HyprlandContext::new());
-    main_context.add_context(TimeContext::new());
-    main_context.add_context(BatteryContext::new());
+    // NOTE lock is held until the daemon exits
+    // Sockets are private, unless the access policy allows the other users to connect
+    let (dir_mode, socket_mode) = if config.access.allows_others() { (0o711, 0o666) } else { (0o700, 0o600) };
 
-    main_context.init().await?;
+    let _instance_lock = match lock_instance(&paths, dir_mode) {
+        Ok(lock)   => lock,
+        Err(error) => {
+            error!("Unable to start the daemon: {error}");
+            return Err(error);
+        },
+    };
 
-    let main_context_shared = Arc::new(Mutex::new(main_context));
-    
-    spawn_listener_loops(main_context_shared.clone()).await?;
+    info!("Socket dir: {}", paths.dir().display());
+
+    let context = Arc::new(ServerContext::new(config_source, config, session));
+
+    // NOTE signal handlers are installed before the service manager is notified
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup    = signal(SignalKind::hangup())?;
+
+    let activated_sockets = systemd::listen_fds()?;
+    let mut bound_sockets = Vec::new();
 
-    let mut interval = time::interval(Duration::from_millis(POLLING_INTERVAL));
+    let call_listener  = open_listener(activated_sockets.call,  paths.call_socket(),  socket_mode, &mut bound_sockets)?;
+    let event_listener = open_listener(activated_sockets.event, paths.event_socket(), socket_mode, &mut bound_sockets)?;
 
-    // Update cycle
+    // Each connection task holds a sender, so the receiver is closed once all of them are finished
+    let (connection_guard, mut connections_closed) = mpsc::channel::<()>(1);
+
+    let listener_loops = spawn_listener_loops(context.clone(), call_listener, event_listener, connection_guard);
+
+    systemd::notify("READY=1");
+
+    // NOTE socket permissions are chosen on startup, so changes of the access policy don't affect them on reload
     loop {
-        main_context_shared.lock().await.update().await?;
-        interval.tick().await;
+        tokio::select! {
+            _ = interrupt.recv() => info!("Got SIGINT, shutting down"),
+            _ = terminate.recv() => info!("Got SIGTERM, shutting down"),
+            _ = hangup.recv()    => {
+                info!("Got SIGHUP, reloading the config");
+                systemd::notify("RELOADING=1");
+
+                if let Some(error) = context.reload().await.error {
+                    error!("Unable to reload the daemon config: {error}");
+                }
+
+                systemd::notify("READY=1");
+                continue;
+            },
+        }
+
+        break;
+    }
+
+    systemd::notify("STOPPING=1");
+
+    for listener_loop in listener_loops {
+        listener_loop.abort();
+    }
+
+    // Event clients are notified with the "rsbar/shutdown" event
+    context.close_connections();
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, connections_closed.recv()).await.is_err() {
+        warn!("Some of the clients were disconnected forcibly");
+    }
+
+    context.stop_contexts().await;
+
+    for socket in bound_sockets {
+        let _ = std::fs::remove_file(socket);
     }
+
+    info!("Daemon is stopped");
+
+    Ok(())
 }
 
-async fn spawn_listener_loops(context: Arc<Mutex<ServerContext>>) -> tokio::io::Result<()> {
-    let context_clone = context.clone();
+// Refuses to start if another daemon is already running with the same socket dir.
+// NOTE mode of an existing socket dir is kept as is, but it has to be private
+fn lock_instance(paths: &SocketPaths, dir_mode: u32) -> std::io::Result<File> {
+    DirBuilder::new().recursive(true).mode(dir_mode).create(paths.dir())?;
+
+    check_private_dir(paths.dir())?;
+
+    // NOTE parent is checked too, e.g. the "/tmp/rsbar-$USER" fallback could be created by another user beforehand.
+    // Shared dirs owned by root (like /tmp) are fine
+    if let Some(parent) = paths.dir().parent().filter(|parent| std::fs::metadata(parent).is_ok_and(|metadata| metadata.uid() != 0)) {
+        check_private_dir(parent)?;
+    }
+
+    let mut lock_file = OpenOptions::new().create(true).truncate(false).write(true).open(paths.lock_file())?;
+
+    match lock_file.try_lock() {
+        Ok(()) => {},
+        Err(TryLockError::WouldBlock) => {
+            return Err(std::io::Error::new(ErrorKind::AlreadyExists, format!("Another daemon is already running in {}", paths.dir().display())));
+        },
+        Err(TryLockError::Error(error)) => return Err(error),
+    }
+
+    lock_file.set_len(0)?;
+    writeln!(lock_file, "{}", std::process::id())?;
+
+    Ok(lock_file)
+}
+
+// Directory must belong to the daemon user and mustn't be writable by the others,
+// otherwise another user would be able to replace the sockets or the lock file
+fn check_private_dir(path: &std::path::Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let euid     = access::owner_uid();
+
+    if !metadata.is_dir() {
+        return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("{} isn't a directory", path.display())));
+    }
+
+    if metadata.uid() != euid {
+        return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("{} is owned by another user (uid {})", path.display(), metadata.uid())));
+    }
+
+    if metadata.mode() & 0o022 != 0 {
+        return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("{} is writable by other users (mode {:o})", path.display(), metadata.mode() & 0o777)));
+    }
+
+    Ok(())
+}
+
+// Sockets passed by systemd are used as is, the other ones are bound by the daemon and removed on shutdown
+fn open_listener(activated: Option<UnixListener>, path: PathBuf, mode: u32, bound_sockets: &mut Vec<PathBuf>) -> std::io::Result<UnixListener> {
+    if let Some(listener) = activated {
+        info!("Using socket passed by systemd instead of {}", path.display());
+        return Ok(listener);
+    }
+
+    let listener = bind_socket(&path, mode)?;
+    bound_sockets.push(path);
+
+    Ok(listener)
+}
 
-    let call_listener = bind_socket("/tmp/rsbar_call.sock")?;
+fn spawn_listener_loops(context: Arc<ServerContext>, call_listener: UnixListener, event_listener: UnixListener, connection_guard: mpsc::Sender<()>) -> Vec<JoinHandle<()>> {
+    let context_clone = context.clone();
+    let connection_guard_clone = connection_guard.clone();
 
-    task::spawn(async move {
+    let call_loop = task::spawn(async move {
         loop {
             match call_listener.accept().await {
-                Ok((stream, _addr)) => { let _ = task::spawn(handle_call_client(stream, context.clone())).await; },
+                Ok((stream, _addr)) => {
+                    if let Some(peer) = accept_peer(&stream, &context) {
+                        let (context, guard) = (context.clone(), connection_guard.clone());
+
+                        task::spawn(async move {
+                            let _guard = guard;
+                            handle_call_client(stream, peer, context).await
+                        });
+                    }
+                },
                 Err(error) => { error!("Client connection failed (call request attempt): {:?}", error); },
             }
         }
     });
 
-    let event_listener = bind_socket("/tmp/rsbar_event.sock")?;
-
-    task::spawn(async move {
+    let event_loop = task::spawn(async move {
         loop {
             match event_listener.accept().await {
-                Ok((stream, _addr)) => { let _ = task::spawn(handle_event_client(stream, context_clone.clone())).await; },
+                Ok((stream, _addr)) => {
+                    if let Some(peer) = accept_peer(&stream, &context_clone) {
+                        let (context, guard) = (context_clone.clone(), connection_guard_clone.clone());
+
+                        task::spawn(async move {
+                            let _guard = guard;
+                            handle_event_client(stream, peer, context).await
+                        });
+                    }
+                },
                 Err(error) => { error!("Client connection failed (event request attempt): {:?}", error); },
             }
         }
     });
 
-    Ok(())
+    vec![call_loop, event_loop]
+}
+
+// Checks credentials of the connected client. Clients without any access are disconnected
+fn accept_peer(stream: &UnixStream, context: &ServerContext) -> Option<Peer> {
+    let peer = match Peer::from_stream(stream) {
+        Ok(peer)   => peer,
+        Err(error) => {
+            warn!("Unable to get client credentials: {error}");
+            return None;
+        },
+    };
+
+    if !context.accepts(&peer) {
+        warn!("Client {peer} was rejected by the access policy");
+        return None;
+    }
+
+    Some(peer)
 }
 
-async fn handle_call_client(stream: UnixStream, context: Arc<Mutex<ServerContext>>) -> tokio::io::Result<()> {
-    let (read_stream, _) = stream.into_split();
-    let mut reader = tokio::io::BufReader::new(read_stream);
+async fn handle_call_client(stream: UnixStream, peer: Peer, context: Arc<ServerContext>) -> tokio::io::Result<()> {
+    let (read_stream, mut write_stream) = stream.into_split();
+    let mut reader = BufReader::new(read_stream);
+    let mut frame_buffer = Vec::new();
+
+    let mut closing = context.closing_signal();
 
-    let mut request_vec = Vec::new();
+    let (version, mut request) = tokio::select! {
+        negotiated = negotiate_protocol(&mut reader, &mut frame_buffer, &mut write_stream) => negotiated?,
+        _ = wait_for_closing(&mut closing) => return Ok(()),
+    };
 
-    while reader.read_until(b'\0', &mut request_vec).await? > 0 {
-        request_vec.pop();
-        let request = String::from_utf8(request_vec.clone()).unwrap();
+    while let Some(frame) = request {
+        info!("Got new call request: {}", frame);
 
-        info!("Got new call request: {}", request);
-        
-        if let Err(response) = context.lock().await.new_call(&request).await {
-            warn!("Invalid request: {request}\n{response}");
+        let reply = match version.decode_call(&frame) {
+            Ok(call)   => context.new_call(&peer, call).await,
+            Err(error) => CallReply::bad_request(error),
+        };
 
-            request_vec.clear();
-            continue;
+        if let CallReply::Error(_, message) = &reply {
+            warn!("Invalid request: {frame}\n{message}");
         }
 
-        request_vec.clear();
+        write_response(&version.encode_reply(&reply), &mut write_stream).await?;
+
+        // NOTE call in progress is always answered, the connection is closed only while waiting for the next one
+        request = tokio::select! {
+            frame = read_frame(&mut reader, &mut frame_buffer) => frame?,
+            _ = wait_for_closing(&mut closing) => None,
+        };
     }
 
     Ok(())
 }
 
-async fn handle_event_client(stream: UnixStream, context: Arc<Mutex<ServerContext>>) -> tokio::io::Result<()> {
+// Subscription requests and events are handled by the same task, so the connection owns its subscriptions.
+// They are removed as soon as the connection is closed
+async fn handle_event_client(stream: UnixStream, peer: Peer, context: Arc<ServerContext>) -> tokio::io::Result<()> {
     let (read_stream, mut write_stream) = stream.into_split();
-    let mut reader = tokio::io::BufReader::new(read_stream);
+    let mut reader = BufReader::new(read_stream);
+    let mut frame_buffer = Vec::new();
+
+    let mut closing = context.closing_signal();
+
+    // NOTE protocol version isn't known yet, so the client is just disconnected
+    let (version, first_request) = tokio::select! {
+        negotiated = negotiate_protocol(&mut reader, &mut frame_buffer, &mut write_stream) => negotiated?,
+        _ = wait_for_closing(&mut closing) => return Ok(()),
+    };
+
+    let mut subscription = context.new_subscription(&peer);
+    let queue = subscription.queue();
+
+    // NOTE events are written by a separate task, so a slow client never blocks the connection task
+    let mut writer = task::spawn(write_events(queue.clone(), version, write_stream));
+    let mut request = Ok(first_request);
+
+    let result = 'connection: loop {
+        let frame = match request {
+            Ok(Some(frame)) => frame,
+            Ok(None)        => break Ok(()),
+            Err(error)      => {
+                warn!("Error occuried while reading subscription request: {error}");
+                break Ok(());
+            },
+        };
+
+        info!("Got new event subscription request: {}", frame);
+
+        let subscription_result = match version.decode_subscription(&frame) {
+            Ok(SubscriptionRequest::Subscribe(pattern))   => context.subscribe(&peer, &mut subscription, pattern).await,
+            Ok(SubscriptionRequest::Unsubscribe(pattern)) => context.unsubscribe(&mut subscription, pattern).map(|_| Vec::new()),
+            Ok(SubscriptionRequest::Snapshot(pattern))    => context.snapshot(&mut subscription, pattern),
+            Ok(SubscriptionRequest::Backpressure(policy)) => {
+                queue.set_backpressure(policy);
+                Ok(Vec::new())
+            },
+            Err(error) => Err(error),
+        };
+
+        let last_values = subscription_result.unwrap_or_else(|error| {
+            warn!("Error occuried while handling subscription request {frame}: {error}");
+            Vec::new()
+        });
+
+        if !last_values.into_iter().all(|event| queue.push(event)) {
+            break Err(queue_overflow());
+        }
+
+        // NOTE read_frame is cancel safe: partially read frame is kept in the frame buffer
+        request = loop {
+            tokio::select! {
+                frame = read_frame(&mut reader, &mut frame_buffer) => break frame,
+                event = subscription.recv() => match event.map(|event| queue.push(event)) {
+                    Some(true)  => {},
+                    Some(false) => break 'connection Err(queue_overflow()),
+                    None        => break 'connection Ok(()),
+                },
+                written = &mut writer => break 'connection written.map_err(std::io::Error::other).and_then(|written| written),
+                _ = wait_for_closing(&mut closing) => {
+                    queue.finish(Event::Shutdown(()).into());
+
+                    return writer.await.map_err(std::io::Error::other)?;
+                },
+            }
+        };
+    };
 
-    let (tx, mut rx) = mpsc::channel::<String>(32);
+    writer.abort();
 
-    tokio::spawn(async move {
-        while let Some(message) = rx.recv().await {
-            info!("New update: {}", message);
+    match result {
+        Ok(())     => info!("Event client disconnected"),
+        Err(error) => warn!("Event client {peer} is disconnected: {error}"),
+    }
 
-            // TODO somehow remove channel from client when it's disconnecting?
-            if let Err(write_result) = write_response(message.as_ref(), &mut write_stream).await {
-                warn!("Error occuried while sending event: {write_result}");            
-    
-                break;
-            }
-        }
-    });
+    Ok(())
+}
+
+async fn wait_for_closing(closing: &mut watch::Receiver<bool>) {
+    let _ = closing.wait_for(|closing| *closing).await;
+}
 
-    let mut request_vec =  Vec::new();
+async fn send_event(event: &ContextEvent, version: ProtocolVersion, write_stream: &mut OwnedWriteHalf) -> tokio::io::Result<()> {
+    let message = version.encode_event(event);
 
-    while reader.read_until(b'\0', &mut request_vec).await? > 0 {
-        request_vec.pop();
-        let request = String::from_utf8(request_vec.clone()).unwrap();
+    info!("New update: {}", message);
 
-        info!("Got new event subscription request: {}", request);
+    if let Err(error) = write_response(&message, write_stream).await {
+        warn!("Error occuried while sending event: {error}");
 
-        if let Err(result) = context.lock().await.new_event_client(&request, tx.clone()).await {
-            warn!("Error occuried while subscribing to event {request}: {result}");
-        }
+        return Err(error);
+    }
 
-        request_vec.clear();
+    Ok(())
+}
+
+// Writes the queued events until the queue is finished. Write timeout is set only by the "disconnect" policy
+async fn write_events(queue: Arc<EventQueue>, version: ProtocolVersion, mut write_stream: OwnedWriteHalf) -> tokio::io::Result<()> {
+    while let Some(event) = queue.pop().await {
+        match queue.write_timeout() {
+            Some(write_timeout) => tokio::time::timeout(write_timeout, send_event(&event, version, &mut write_stream)).await
+                .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "Client doesn't read its events"))??,
+            None => send_event(&event, version, &mut write_stream).await?,
+        }
     }
 
     Ok(())
 }
 
-fn bind_socket(path: impl AsRef<std::path::Path>) -> std::io::Result<UnixListener> {
+fn queue_overflow() -> std::io::Error {
+    std::io::Error::new(ErrorKind::OutOfMemory, "Event queue is full")
+}
+
+// Reads the first frame of the connection and answers it if it's a hello request.
+// Returns the negotiated protocol version and the first regular request
+async fn negotiate_protocol(reader: &mut BufReader<OwnedReadHalf>, frame_buffer: &mut Vec<u8>, write_stream: &mut OwnedWriteHalf) -> tokio::io::Result<(ProtocolVersion, Option<String>)> {
+    let first_frame = read_frame(reader, frame_buffer).await?;
+
+    if let Some(version) = first_frame.as_deref().and_then(ProtocolVersion::from_hello) {
+        info!("Negotiated protocol version: {}", version as u32);
+
+        write_response(&version.hello_reply(), write_stream).await?;
+
+        return Ok((version, read_frame(reader, frame_buffer).await?));
+    }
+
+    Ok((ProtocolVersion::V1Text, first_frame))
+}
+
+// Returns None if the client has disconnected.
+// Frame buffer keeps a partially read frame, if the future is cancelled
+async fn read_frame(reader: &mut BufReader<OwnedReadHalf>, frame_buffer: &mut Vec<u8>) -> tokio::io::Result<Option<String>> {
+    if reader.read_until(FRAME_DELIMITER, frame_buffer).await? == 0 && frame_buffer.is_empty() {
+        return Ok(None);
+    }
+
+    let mut frame = std::mem::take(frame_buffer);
+
+    if frame.last() == Some(&FRAME_DELIMITER) {
+        frame.pop();
+    }
+
+    Ok(Some(String::from_utf8_lossy(&frame).into_owned()))
+}
+
+fn bind_socket(path: impl AsRef<std::path::Path>, mode: u32) -> std::io::Result<UnixListener> {
     let path = path.as_ref();
 
+    // NOTE socket could only be left by a dead daemon, because the instance is locked
     let _ = std::fs::remove_file(path);
 
-    UnixListener::bind(path)
+    // NOTE socket is bound with a restrictive umask, so it isn't accessible by the others before its mode is set
+    let previous_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+
+    unsafe { libc::umask(previous_umask) };
+
+    let listener = listener?;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+
+    Ok(listener)
 }
 
-async fn write_response(response: &str, stream: &mut tokio::net::unix::OwnedWriteHalf) -> tokio::io::Result<()> {
-    stream.write(response.as_bytes()).await?;
-    stream.write(b"\0").await?;
+async fn write_response(response: &str, stream: &mut OwnedWriteHalf) -> tokio::io::Result<()> {
+    stream.write_all(response.as_bytes()).await?;
+    stream.write_all(&[FRAME_DELIMITER]).await?;
     stream.flush().await?;
     
     Ok(())
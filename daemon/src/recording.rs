@@ -0,0 +1,230 @@
+use std::{collections::BTreeSet, io::ErrorKind, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use log::{error, info, warn};
+use rsbar_protocol::{take_flag, CallReply, ContextCall, ContextEvent, ContextId, ContextKind, EventKind, ProtocolVersion};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{fs::File, io::{AsyncWriteExt, BufWriter}, sync::{broadcast::error::RecvError, mpsc, Notify}, time::{sleep_until, Instant}};
+
+use crate::rsbar_context::EventHandler;
+
+const RECORD_FLAG: &str = "--record";
+const REPLAY_FLAG: &str = "--replay";
+const SPEED_FLAG: &str = "--speed";
+
+// Line of a recording. Frames are stored in the v2 wire format, time is counted in milliseconds since the recording has started
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Record {
+    Event { time: u64, frame: Value },
+    Call { time: u64, request: Value, reply: Value },
+}
+
+// Daemon either runs the configured contexts (optionally recording them) or replays a recording instead of them
+pub enum Session {
+    Live(Option<Recorder>),
+    Replay(Replay),
+}
+
+// Writes the emitted events and the received calls to a file, records are sent to the writer task
+pub struct Recorder {
+    sender: mpsc::UnboundedSender<Record>,
+    start:  Instant,
+}
+
+// Recorded events are triggered again with the original timing divided by the speed factor
+pub struct Replay {
+    steps: Vec<(u64, ReplayStep)>,
+    speed: f64,
+}
+
+enum ReplayStep {
+    Event(ContextEvent),
+    Call(String),
+}
+
+// Contexts of the replayed recording. Replay starts with the first subscription, so the client doesn't miss its beginning
+pub struct ReplayState {
+    contexts: Vec<ContextId>,
+    start:    Arc<Notify>,
+}
+
+impl Session {
+    pub fn from_args(args: &mut Vec<String>) -> std::io::Result<Self> {
+        let record = take_flag(args, RECORD_FLAG)?;
+        let replay = take_flag(args, REPLAY_FLAG)?;
+        let speed  = take_flag(args, SPEED_FLAG)?;
+
+        match (record, replay, speed) {
+            (Some(_), Some(_), _)     => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("{RECORD_FLAG} and {REPLAY_FLAG} flags can't be used together"))),
+            (_, None, Some(_))        => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("{SPEED_FLAG} flag is used only with {REPLAY_FLAG}"))),
+            (Some(path), None, None)  => Ok(Session::Live(Some(Recorder::create(Path::new(&path))?))),
+            (None, Some(path), speed) => Ok(Session::Replay(Replay::load(Path::new(&path), parse_speed(speed)?)?)),
+            (None, None, None)        => Ok(Session::Live(None)),
+        }
+    }
+}
+
+impl Recorder {
+    // NOTE file is created right away, so a bad path is reported on startup
+    fn create(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path).map_err(|error| std::io::Error::new(error.kind(), format!("Unable to create {}: {error}", path.display())))?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        info!("Recording the session to {}", path.display());
+
+        tokio::spawn(write_records(File::from_std(file), path.to_path_buf(), receiver));
+
+        Ok(Recorder { sender, start: Instant::now() })
+    }
+
+    // Events are taken from the broadcast channel, so recording doesn't slow down the contexts
+    pub fn record_events(&self, event_handler: &EventHandler) {
+        let mut receiver = event_handler.events();
+        let sender = self.sender.clone();
+        let start  = self.start;
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        let record = Record::Event { time: elapsed(start), frame: to_json(ProtocolVersion::V2Json.encode_event(&event)) };
+
+                        if sender.send(record).is_err() {
+                            break;
+                        }
+                    },
+                    Err(RecvError::Lagged(count)) => warn!("Recording has missed {count} events"),
+                    Err(RecvError::Closed)        => break,
+                }
+            }
+        });
+    }
+
+    pub fn record_call(&self, request: &ContextCall, reply: &CallReply) {
+        let _ = self.sender.send(Record::Call {
+            time:    elapsed(self.start),
+            request: to_json(ProtocolVersion::V2Json.encode_call(request.clone())),
+            reply:   to_json(ProtocolVersion::V2Json.encode_reply(reply)),
+        });
+    }
+}
+
+impl Replay {
+    // NOTE whole recording is decoded on startup, so a broken file is reported before the clients are served
+    fn load(path: &Path, speed: f64) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|error| std::io::Error::new(error.kind(), format!("Unable to read {}: {error}", path.display())))?;
+        let mut steps = Vec::new();
+
+        for (index, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let bad_record = |error: String| std::io::Error::new(ErrorKind::InvalidData, format!("Bad record on line {} of {}: {error}", index + 1, path.display()));
+
+            let step = match serde_json::from_str(line).map_err(|error| bad_record(error.to_string()))? {
+                Record::Event { time, frame } => {
+                    let event = ProtocolVersion::V2Json.decode_event(&frame.to_string()).map_err(|error| bad_record(error.to_string()))?;
+
+                    // Daemon publishes the status of its own subscribers
+                    if event.event.kind() == EventKind::Subscribers {
+                        continue;
+                    }
+
+                    (time, ReplayStep::Event(event))
+                },
+                Record::Call { time, request, reply } => {
+                    let call = ProtocolVersion::V2Json.decode_call(&request.to_string()).map_err(|error| bad_record(error.to_string()))?;
+
+                    (time, ReplayStep::Call(format!("{call} -> {reply}")))
+                },
+            };
+
+            steps.push(step);
+        }
+
+        steps.sort_by_key(|(time, _)| *time);
+
+        info!("Replaying {} at {speed}x speed", path.display());
+
+        Ok(Replay { steps, speed })
+    }
+
+    pub fn spawn(self, event_handler: Arc<EventHandler>) -> ReplayState {
+        let contexts: BTreeSet<ContextId> = self.steps.iter()
+            .filter_map(|(_, step)| match step {
+                ReplayStep::Event(event) => Some(event.context.clone()),
+                ReplayStep::Call(_)      => None,
+            })
+            .filter(|context_id| context_id.kind() != ContextKind::Rsbar)
+            .collect();
+
+        let start = Arc::new(Notify::new());
+
+        tokio::spawn(self.run(event_handler, start.clone()));
+
+        ReplayState { contexts: contexts.into_iter().collect(), start }
+    }
+
+    async fn run(self, event_handler: Arc<EventHandler>, start: Arc<Notify>) {
+        start.notified().await;
+
+        info!("Replay is started");
+
+        let start_time = Instant::now();
+
+        for (time, step) in self.steps {
+            sleep_until(start_time + Duration::from_secs_f64(time as f64 / 1000.0 / self.speed)).await;
+
+            match step {
+                ReplayStep::Event(event) => event_handler.trigger_event(event),
+                ReplayStep::Call(call)   => info!("Recorded call: {call}"),
+            }
+        }
+
+        info!("Replay is finished, the last values are kept");
+    }
+}
+
+impl ReplayState {
+    pub fn contexts(&self) -> &[ContextId] {
+        &self.contexts
+    }
+
+    // NOTE repeated notifications are ignored once the replay is started
+    pub fn start(&self) {
+        self.start.notify_one();
+    }
+}
+
+// NOTE each record is flushed, so the recording is usable even if the daemon is killed
+async fn write_records(file: File, path: PathBuf, mut receiver: mpsc::UnboundedReceiver<Record>) {
+    let mut writer = BufWriter::new(file);
+
+    while let Some(record) = receiver.recv().await {
+        let mut line = serde_json::to_string(&record).unwrap_or_default();
+        line.push('\n');
+
+        if let Err(error) = async { writer.write_all(line.as_bytes()).await?; writer.flush().await }.await {
+            error!("Unable to write the recording to {}: {error}", path.display());
+            return;
+        }
+    }
+}
+
+fn parse_speed(speed: Option<String>) -> std::io::Result<f64> {
+    let Some(speed) = speed else {
+        return Ok(1.0);
+    };
+
+    match speed.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Bad value of the {SPEED_FLAG} flag: {speed}, a positive number is expected"))),
+    }
+}
+
+fn elapsed(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
+
+// NOTE v2 frames are always valid JSON
+fn to_json(frame: String) -> Value {
+    serde_json::from_str(&frame).unwrap_or_default()
+}
@@ -1,53 +1,397 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, RwLock}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
 use async_trait::async_trait;
 
-use tokio::sync::{mpsc, Mutex};
+use log::warn;
+use rsbar_protocol::{Call, ContextDescription, ContextEvent, ContextId, Event, EventAddress, EventKind, EventStamp, SubscriberStatus, SubscribersStatus};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
 
+use crate::{access::{AccessPolicy, Peer}, event_queue::{EventQueue, SubscriberSettings}, history::EventHistory};
+
+const EVENT_QUEUE_SIZE: usize = 256;
+
+// Fan-out of the context events: each event is broadcasted to all of the connections
+// and every connection filters them by its own subscriptions (see Subscription).
+// Contexts never wait for the clients
 pub struct EventHandler {
-    events: HashMap<String, Vec<mpsc::Sender<String>>>,
-} 
+    sender:         broadcast::Sender<ContextEvent>,
+    // Last triggered value of each event
+    last_values:    RwLock<HashMap<EventAddress, ContextEvent>>,
+    // Sequence number of the last sent value of each event. NOTE it's kept when a context is stopped,
+    // so the restarted context continues the sequence
+    sequences:      Mutex<HashMap<EventAddress, u64>>,
+    history:        EventHistory,
+    // Subscribed events of each connection
+    subscriptions:  Mutex<HashMap<u64, HashSet<EventAddress>>>,
+    // Outgoing queue of each connection with its peer
+    queues:         Mutex<HashMap<u64, (String, Arc<EventQueue>)>>,
+    next_client_id: AtomicU64,
+}
+
+// Triggers events on behalf of a context instance according to the event policies of the context
+#[derive(Clone)]
+pub struct EventEmitter {
+    context:       ContextId,
+    policies:      Arc<HashMap<EventKind, EventPolicy>>,
+    throttles:     Arc<Mutex<HashMap<EventKind, Throttle>>>,
+    event_handler: Arc<EventHandler>,
+}
+
+// Defines which of the triggered events are sent to the clients.
+// NOTE events of the daemon itself (e.g. reload reports) are always sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPolicy {
+    // Event is sent only if its value differs from the last sent one
+    OnChange,
+    // Changed values are sent at most once per period. Values triggered within the period are coalesced:
+    // only the last one is sent at the end of the period
+    Throttle(Duration),
+}
+
+#[derive(Default)]
+struct Throttle {
+    last_sent: Option<Instant>,
+    // Coalesced value, it's sent by the scheduled flush
+    pending:   Option<ContextEvent>,
+}
+
+// Subscriptions of a single connection. Connection gets each event only once,
+// no matter how many times it has subscribed to it
+pub struct Subscription {
+    client_id:     u64,
+    peer:          Peer,
+    // NOTE policy is shared with the server context, so the subscriptions follow its reloads
+    access:        Arc<RwLock<AccessPolicy>>,
+    events:        HashMap<EventAddress, usize>,
+    // Sequence numbers of the cached values sent to the connection, older events still buffered in the receiver are skipped
+    sent_values:   HashMap<EventAddress, u64>,
+    receiver:      broadcast::Receiver<ContextEvent>,
+    queue:         Arc<EventQueue>,
+    event_handler: Arc<EventHandler>,
+}
 
 impl EventHandler {
     pub fn new() -> Self{
-        return EventHandler {
-            events: HashMap::new(),
+        EventHandler {
+            sender:         broadcast::channel(EVENT_QUEUE_SIZE).0,
+            last_values:    RwLock::new(HashMap::new()),
+            sequences:      Mutex::new(HashMap::new()),
+            history:        EventHistory::new(),
+            subscriptions:  Mutex::new(HashMap::new()),
+            queues:         Mutex::new(HashMap::new()),
+            next_client_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn subscribe(self: &Arc<Self>, peer: Peer, access: Arc<RwLock<AccessPolicy>>, settings: SubscriberSettings) -> Subscription {
+        let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let queue     = Arc::new(EventQueue::new(settings));
+
+        self.queues.lock().unwrap().insert(client_id, (peer.to_string(), queue.clone()));
+
+        Subscription {
+            peer,
+            access,
+            events:        HashMap::new(),
+            sent_values:   HashMap::new(),
+            receiver:      self.sender.subscribe(),
+            event_handler: self.clone(),
+            client_id,
+            queue,
+        }
+    }
+
+    pub fn emitter(self: &Arc<Self>, context: ContextId, policies: HashMap<EventKind, EventPolicy>) -> EventEmitter {
+        EventEmitter {
+            context,
+            policies:      Arc::new(policies),
+            throttles:     Arc::new(Mutex::new(HashMap::new())),
+            event_handler: self.clone(),
+        }
+    }
+
+    // All of the emitted events regardless of the subscriptions, e.g. for the session recording
+    pub fn events(&self) -> broadcast::Receiver<ContextEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn history(&self) -> &EventHistory {
+        &self.history
+    }
+
+    pub fn last_value(&self, address: &EventAddress) -> Option<ContextEvent> {
+        self.last_values.read().unwrap().get(address).cloned()
+    }
+
+    // Returns subscribed connections count of each event
+    pub fn subscriptions(&self) -> HashMap<EventAddress, usize> {
+        let mut subscriptions = HashMap::new();
+
+        for event in self.subscriptions.lock().unwrap().values().flatten() {
+            *subscriptions.entry(event.clone()).or_default() += 1;
+        }
+
+        subscriptions
+    }
+
+    // Queue policy and dropped events count of each connection
+    pub fn subscribers(&self) -> SubscribersStatus {
+        let subscribers = self.queues.lock().unwrap().iter()
+            .map(|(client_id, (peer, queue))| (client_id.to_string(), SubscriberStatus {
+                peer:         peer.clone(),
+                backpressure: queue.backpressure(),
+                dropped:      queue.dropped(),
+            }))
+            .collect();
+
+        SubscribersStatus(subscribers)
+    }
+
+    // Drops the cached values of a stopped context, so they aren't sent to the new subscribers
+    pub fn forget(&self, context: &ContextId) {
+        self.last_values.write().unwrap().retain(|address, _| address.context != *context);
+    }
+
+    pub fn trigger_event(&self, event: ContextEvent) {
+        self.send(event, false);
+    }
+
+    // Returns false if the value hasn't changed, such event isn't sent
+    pub fn trigger_changed_event(&self, event: ContextEvent) -> bool {
+        self.send(event, true)
+    }
+
+    // NOTE cache is locked while the event is sent, so the clients get the events in the order of the cached values
+    fn send(&self, mut event: ContextEvent, only_changed: bool) -> bool {
+        let mut last_values = self.last_values.write().unwrap();
+        let address = event.address();
+
+        if only_changed && last_values.get(&address).is_some_and(|last_value| last_value.event == event.event) {
+            return false;
+        }
+
+        let mut sequences = self.sequences.lock().unwrap();
+        let seq = sequences.entry(address.clone()).or_default();
+
+        *seq += 1;
+        event.stamp = Some(EventStamp { seq: *seq, timestamp: timestamp(), coalesced: 0 });
+
+        self.history.record(&event);
+        last_values.insert(address, event.clone());
+
+        // NOTE sending fails only if there are no connections
+        let _ = self.sender.send(event);
+
+        true
+    }
+}
+
+impl EventEmitter {
+    pub fn trigger_event(&self, event: Event) {
+        let policy = self.policies.get(&event.kind()).copied().unwrap_or(EventPolicy::OnChange);
+        let event  = ContextEvent { context: self.context.clone(), event, stamp: None };
+
+        match policy {
+            EventPolicy::OnChange         => { self.event_handler.trigger_changed_event(event); },
+            EventPolicy::Throttle(period) => self.throttle(event, period),
         }
     }
 
-    pub fn add_event(&mut self, name: &str, client: mpsc::Sender<String>) {
-        self.events.entry(name.to_string()).or_insert(Vec::new()).push(client);
+    // The first change is sent right away, the following ones are coalesced until the end of the period
+    fn throttle(&self, event: ContextEvent, period: Duration) {
+        let kind = event.event.kind();
+        let mut throttles = self.throttles.lock().unwrap();
+        let throttle = throttles.entry(kind).or_default();
+
+        // Flush is already scheduled
+        if throttle.pending.is_some() {
+            throttle.pending = Some(event);
+            return;
+        }
+
+        let now = Instant::now();
+
+        match throttle.last_sent {
+            Some(last_sent) if now < last_sent + period => {
+                throttle.pending = Some(event);
+
+                let emitter = self.clone();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep_until((last_sent + period).into()).await;
+                    emitter.flush(kind);
+                });
+            },
+            _ => {
+                if self.event_handler.trigger_changed_event(event) {
+                    throttle.last_sent = Some(now);
+                }
+            },
+        }
     }
 
-    pub async fn trigger_event(&self, name: &str, data: &str) {
-        let clients = self.events.get(name);
-        
-        if clients.is_none() {
+    fn flush(&self, kind: EventKind) {
+        let mut throttles = self.throttles.lock().unwrap();
+
+        let Some(throttle) = throttles.get_mut(&kind) else {
             return;
+        };
+
+        if let Some(event) = throttle.pending.take() {
+            if self.event_handler.trigger_changed_event(event) {
+                throttle.last_sent = Some(Instant::now());
+            }
         }
+    }
+}
+
+impl Subscription {
+    // Returns false if the connection is already subscribed to the event (only the reference count is increased)
+    pub fn add_event(&mut self, address: EventAddress) -> bool {
+        let references = self.events.entry(address).or_default();
+        *references += 1;
 
-        for client in clients.unwrap() {
+        if *references > 1 {
+            return false;
+        }
+
+        self.update_registry();
+
+        true
+    }
+
+    // Connection is unsubscribed when all of its references are removed.
+    // Returns false if the connection wasn't subscribed to the event
+    pub fn remove_event(&mut self, address: &EventAddress) -> bool {
+        let Some(references) = self.events.get_mut(address) else {
+            return false;
+        };
+
+        *references -= 1;
+
+        if *references == 0 {
+            self.events.remove(address);
+            self.sent_values.remove(address);
+            self.update_registry();
+        }
+
+        true
+    }
+
+    // Returns None if the event handler is closed
+    pub async fn recv(&mut self) -> Option<ContextEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if self.is_wanted(&event) => return Some(event),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Connection {} is too slow, {count} events were dropped", self.client_id);
+                    self.queue.add_dropped(count);
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
 
-            let response = format!("{}/{}", name, data);
-            let _ = client.send(response).await;
+    // Cached value is sent apart from the broadcast, so the events received before it mustn't follow it
+    pub fn mark_sent(&mut self, event: &ContextEvent) {
+        if let Some(stamp) = event.stamp {
+            let sent_seq = self.sent_values.entry(event.address()).or_default();
+            *sent_seq = (*sent_seq).max(stamp.seq);
         }
     }
+
+    pub fn contains(&self, address: &EventAddress) -> bool {
+        self.events.contains_key(address)
+    }
+
+    pub fn queue(&self) -> Arc<EventQueue> {
+        self.queue.clone()
+    }
+
+    // Event is delivered if it's subscribed, still readable by the peer (the policy may be reloaded)
+    // and newer than the cached value sent to the connection
+    fn is_wanted(&mut self, event: &ContextEvent) -> bool {
+        let address = event.address();
+
+        if !self.events.contains_key(&address) || !self.access.read().unwrap().can_read(&self.peer, &address.context) {
+            return false;
+        }
+
+        match (self.sent_values.get(&address), event.stamp) {
+            (Some(sent_seq), Some(stamp)) if stamp.seq <= *sent_seq => false,
+            _ => {
+                // NOTE events are broadcasted in order, so the following ones are newer anyway
+                self.sent_values.remove(&address);
+                true
+            },
+        }
+    }
+
+    fn update_registry(&self) {
+        let events = self.events.keys().cloned().collect();
+
+        self.event_handler.subscriptions.lock().unwrap().insert(self.client_id, events);
+    }
+}
+
+// Subscriptions are removed as soon as the connection is closed
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.event_handler.subscriptions.lock().unwrap().remove(&self.client_id);
+        self.event_handler.queues.lock().unwrap().remove(&self.client_id);
+    }
+}
+
+// Milliseconds since the unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or_default()
+}
+
+const DEFAULT_UPDATE_INTERVAL: u64 = 1000;
+
+// Defines when the server core calls the context's update method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateSchedule {
+    // Update every period
+    Interval(Duration),
+    // Update at each period boundary of the wall clock (e.g. at the start of each second)
+    Aligned(Duration),
+    // Never updated by the server core: the context triggers events itself (on calls or by its own listeners)
+    OnDemand,
 }
 
 #[async_trait]
 pub trait RsbarContextContent {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()>;
+    // Events are triggered through the emitter of the context instance
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()>;
     async fn update(&mut self) -> tokio::io::Result<()>;
 
+    // Each context is updated concurrently with the others
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::Interval(Duration::from_millis(DEFAULT_UPDATE_INTERVAL))
+    }
+
     async fn force_events(&mut self) -> tokio::io::Result<()>;
 
     // Event socket:
-    // Event subscription format: "<context name>/<parameter name>"
-    // Event response format:     "<parameter content>" or None in case of error
+    // Contexts trigger typed events (see rsbar_protocol::Event), which are encoded by the server core
+    // according to the protocol version of each client
 
     // Method socket:
-    // Call args format:   "<context name>/<procedure name>/<arg string>"
-    async fn call(&mut self, procedure: &str, args: &str) -> tokio::io::Result<()>;
+    // Only calls addressed to the context are passed (see rsbar_protocol::Call).
+    // Returns an optional procedure result. Bad arguments should be reported with ErrorKind::InvalidInput
+    // and unknown procedures with ErrorKind::Unsupported, so the caller gets a proper error code
+    async fn call(&mut self, call: Call) -> tokio::io::Result<Option<Value>>;
+
+    // Events are sent to the clients only if their values are changed, unless the context declares another policy
+    fn event_policy(&self, _kind: EventKind) -> EventPolicy {
+        EventPolicy::OnChange
+    }
+
+    // Introspection: events the context triggers and procedures it accepts (see the "rsbar/describe" call)
+    fn describe(&self) -> ContextDescription;
 }
 
 pub struct RsbarContext {
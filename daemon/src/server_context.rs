@@ -1,82 +1,431 @@
-use std::{collections::HashMap, io::ErrorKind, sync::Arc};
+use std::{collections::{BTreeMap, HashMap, HashSet}, io::ErrorKind, sync::{Arc, RwLock}, time::Duration};
 
-use tokio::sync::{mpsc, Mutex};
+use log::info;
+use rsbar_protocol::{Call, CallReply, ContextCall, ContextDescription, ContextEvent, ContextId, ContextKind, ErrorCode, Event, EventAddress, EventDescription, EventKind, EventPattern};
+use rsbar_protocol::{HistoryQuery, ProcedureDescription, ReloadReport, ValueDescription, ValueType};
+use serde_json::Value;
+use tokio::{sync::{watch, Mutex}, time::interval};
 
-use crate::rsbar_context::{EventHandler, RsbarContext};
+use crate::{access::{AccessPolicy, Peer}, config::{ConfigSource, ContextSettings, DaemonConfig}, event_queue::SubscriberSettings, rsbar_context::{EventHandler, RsbarContext, Subscription}};
+use crate::recording::{Recorder, ReplayState, Session};
+use crate::supervisor::{ContextHandle, Supervisor};
 
-const EVENT_REQUEST_PARTS: usize = 2;
-const CALL_REQUEST_PARTS:  usize = 3;
+// Subscribers status is published at most once per this interval
+const SUBSCRIBERS_STATUS_INTERVAL: u64 = 1000;
 
+// Routes requests of the clients to the context tasks. Contexts and access policy are replaced only on reload,
+// so connections are served concurrently and the locks are never held across await points
 pub struct ServerContext {
-    contexts:      HashMap<String, RsbarContext>,
-    event_handler: Arc<Mutex<EventHandler>>,
+    contexts:      RwLock<HashMap<ContextId, RunningContext>>,
+    event_handler: Arc<EventHandler>,
+    supervisor:    Arc<Supervisor>,
+    access:        Arc<RwLock<AccessPolicy>>,
+    subscribers:   RwLock<SubscriberSettings>,
+    closing:       watch::Sender<bool>,
+    config_source: ConfigSource,
+    reloading:     Mutex<()>,
+    recorder:      Option<Recorder>,
+    // NOTE contexts aren't started while a recording is replayed
+    replay:        Option<ReplayState>,
+}
+
+// Settings are kept to find out which contexts are changed on reload
+struct RunningContext {
+    handle:      ContextHandle,
+    settings:    ContextSettings,
+    description: ContextDescription,
 }
 
 impl ServerContext {
-    pub fn new() -> Self {
-        ServerContext { 
-            contexts:      HashMap::new(),
-            event_handler: Arc::new(Mutex::new(EventHandler::new())),
+    pub fn new(config_source: ConfigSource, config: DaemonConfig, session: Session) -> Self {
+        let event_handler = Arc::new(EventHandler::new());
+
+        event_handler.history().set_settings(config.history);
+
+        let (recorder, replay) = match session {
+            Session::Live(recorder) => (recorder, None),
+            Session::Replay(replay) => (None, Some(replay.spawn(event_handler.clone()))),
+        };
+
+        if let Some(recorder) = &recorder {
+            recorder.record_events(&event_handler);
+        }
+
+        let server_context = ServerContext {
+            contexts:    RwLock::new(HashMap::new()),
+            supervisor:  Arc::new(Supervisor::new(event_handler.clone())),
+            access:      Arc::new(RwLock::new(config.access)),
+            subscribers: RwLock::new(config.subscribers),
+            closing:     watch::Sender::new(false),
+            reloading:   Mutex::new(()),
+            event_handler,
+            config_source,
+            recorder,
+            replay,
+        };
+
+        tokio::spawn(publish_subscribers(server_context.event_handler.clone()));
+
+        if server_context.replay.is_none() {
+            for (context_id, settings) in config.contexts.enabled() {
+                server_context.start_context(context_id, settings);
+            }
         }
+
+        server_context
+    }
+
+    // Context is started (and updated) by its own supervised task, so a failing context doesn't affect the others
+    fn start_context(&self, context_id: ContextId, settings: ContextSettings) {
+        let context     = settings.clone().into_context();
+        let description = context.describe();
+        let handle      = self.supervisor.spawn(&context_id, RsbarContext::new(context));
+
+        self.contexts.write().unwrap().insert(context_id, RunningContext { handle, settings, description });
+    }
+
+    async fn stop_context(&self, context_id: &ContextId) {
+        self.contexts.write().unwrap().remove(context_id);
+        self.supervisor.stop_context(context_id).await;
+        self.event_handler.forget(context_id);
     }
 
-    pub async fn init(&mut self) -> tokio::io::Result<()>{
-        for (_context_name, context) in self.contexts.iter_mut() {
-            context.context.init(self.event_handler.clone()).await?;
+    // Clients without any permissions are disconnected right away
+    pub fn accepts(&self, peer: &Peer) -> bool {
+        self.access.read().unwrap().has_any_access(peer)
+    }
+
+    // Re-reads the config and restarts only the changed contexts. Subscriptions are kept,
+    // so the clients get the events of the restarted contexts as soon as they are triggered again.
+    // NOTE a bad config is reported and the running contexts are left as is
+    pub async fn reload(&self) -> ReloadReport {
+        let _reloading = self.reloading.lock().await;
+        let mut report = ReloadReport::default();
+
+        let config = match self.config_source.load() {
+            Ok(config) => config,
+            Err(error) => {
+                report.error = Some(error.to_string());
+                self.event_handler.trigger_event(Event::Reload(report.clone()).into());
+                return report;
+            },
+        };
+
+        // NOTE connected clients keep their queue settings, while their subscriptions are checked against the new policy on each event
+        *self.access.write().unwrap()      = config.access;
+        *self.subscribers.write().unwrap() = config.subscribers;
+
+        self.event_handler.history().set_settings(config.history);
+
+        let mut new_contexts: HashMap<ContextId, ContextSettings> = match self.replay {
+            Some(_) => HashMap::new(),
+            None    => config.contexts.enabled().collect(),
+        };
+        let running_contexts: Vec<(ContextId, ContextSettings)> = self.contexts.read().unwrap().iter()
+            .map(|(context_id, context)| (context_id.clone(), context.settings.clone()))
+            .collect();
+
+        for (context_id, settings) in running_contexts {
+            match new_contexts.remove(&context_id) {
+                Some(new_settings) if new_settings == settings => report.unchanged.push(context_id.to_string()),
+                Some(new_settings) => {
+                    self.stop_context(&context_id).await;
+                    self.start_context(context_id.clone(), new_settings);
+                    report.reconfigured.push(context_id.to_string());
+                },
+                None => {
+                    self.stop_context(&context_id).await;
+                    report.stopped.push(context_id.to_string());
+                },
+            }
         }
 
-        Ok(())
+        for (context_id, settings) in new_contexts {
+            report.started.push(context_id.to_string());
+            self.start_context(context_id, settings);
+        }
+
+        for contexts in [&mut report.started, &mut report.stopped, &mut report.reconfigured, &mut report.unchanged] {
+            contexts.sort();
+        }
+
+        info!("Config is reloaded: {}", serde_json::to_string(&report).unwrap_or_default());
+
+        self.event_handler.trigger_event(Event::Reload(report.clone()).into());
+
+        report
+    }
+
+    // Connections are closed as soon as the daemon starts shutting down
+    pub fn closing_signal(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    pub fn close_connections(&self) {
+        self.closing.send_replace(true);
+    }
+
+    pub async fn stop_contexts(&self) {
+        self.supervisor.stop().await;
+    }
+
+    pub fn new_subscription(&self, peer: &Peer) -> Subscription {
+        self.event_handler.subscribe(*peer, self.access.clone(), self.subscribers.read().unwrap().clone())
+    }
+
+    // Calls are recorded along with their replies
+    pub async fn new_call(&self, peer: &Peer, request: ContextCall) -> CallReply {
+        let Some(recorder) = &self.recorder else {
+            return self.perform_call(peer, request).await;
+        };
+
+        let reply = self.perform_call(peer, request.clone()).await;
+        recorder.record_call(&request, &reply);
+
+        reply
     }
 
-    pub async fn new_call(&mut self, request: &str) -> tokio::io::Result<()> {
-        let request_parts = split_request(request, CALL_REQUEST_PARTS)?;
+    async fn perform_call(&self, peer: &Peer, request: ContextCall) -> CallReply {
+        let ContextCall { context: context_id, call } = request;
+
+        // NOTE introspection, query and history need the read permission of the events instead of the call permission,
+        // their results are limited to the readable contexts
+        match &call {
+            Call::Query(patterns) => return self.query(peer, patterns).into(),
+            Call::History(query)  => return self.history(peer, query).into(),
+            Call::Describe        => return self.describe(peer).into(),
+            Call::Subscriptions   => return self.subscriptions(peer).into(),
+            _                     => {},
+        }
+
+        // NOTE reload restarts the contexts of the daemon owner, so the call permission isn't enough
+        if matches!(call, Call::Reload) && !peer.is_owner() {
+            return CallReply::error(ErrorCode::PermissionDenied, format!("Client {peer} isn't allowed to reload the daemon config, only its owner is"));
+        }
+
+        if !self.access.read().unwrap().can_call(peer, &context_id) {
+            return CallReply::error(ErrorCode::PermissionDenied, format!("Client {peer} isn't allowed to call {context_id}/{}", call.procedure()));
+        }
+
+        if context_id == ContextKind::Rsbar.into() {
+            return self.builtin_call(call).await.into();
+        }
+
+        if self.replay.is_some() {
+            return CallReply::error(ErrorCode::Failed, format!("Calls of {context_id} aren't performed while a recording is replayed"));
+        }
 
-        if let Some(context) = self.contexts.get_mut(request_parts[0]) {
-            (*context).context.call(request_parts[1], request_parts[2]).await?;
-             
-            return Ok(());
+        // NOTE handle is cloned, so the contexts lock isn't held while the call is processed
+        if let Some(handle) = self.context_handle(&context_id) {
+            return handle.call(call).await.into();
         }
 
-        Err(std::io::Error::new(ErrorKind::Other, format!("Can't get context by name {}", request_parts[0])))
+        CallReply::error(ErrorCode::UnknownContext, format!("Can't get context by name {context_id}"))
     }
 
-    pub async fn new_event_client(&mut self, request: &str, stream: mpsc::Sender<String>) -> tokio::io::Result<()> {
-        let request_parts = split_request(request, EVENT_REQUEST_PARTS)?;
-        
-        if let Some(context) = self.contexts.get_mut(request_parts[0]) {
-            self.event_handler.lock().await.add_event(request, stream);
-            
-            let _ = context.context.force_events().await;
-            
-            return Ok(());
+    // Returns the last values of the newly subscribed events, they should be sent to this client only.
+    // Events the client isn't allowed to read are skipped
+    pub async fn subscribe(&self, peer: &Peer, subscription: &mut Subscription, pattern: EventPattern) -> tokio::io::Result<Vec<ContextEvent>> {
+        let events = self.readable_events(peer, &pattern)?;
+
+        let mut last_values = Vec::new();
+        let mut uncached_contexts = HashSet::new();
+
+        // NOTE repeated subscriptions only increase the reference count
+        for event in events {
+            if !subscription.add_event(event.clone()) {
+                continue;
+            }
+
+            match self.event_handler.last_value(&event) {
+                Some(value) => {
+                    subscription.mark_sent(&value);
+                    last_values.push(value);
+                },
+                None        => { uncached_contexts.insert(event.context); },
+            }
+        }
+
+        // Context hasn't triggered the event yet (e.g. it's still starting)
+        for context_id in uncached_contexts {
+            if let Some(handle) = self.context_handle(&context_id) {
+                handle.force_events().await;
+            }
+        }
+
+        if let Some(replay) = &self.replay {
+            replay.start();
         }
 
-        Err(std::io::Error::new(ErrorKind::Other, format!("Can't get context by name {}", request_parts[0])))
+        Ok(last_values)
     }
 
-    pub fn add_context(&mut self, (context_name, context): (String, RsbarContext)) {
-        self.contexts.insert(context_name, context);
+    // Returns the last values of the subscribed events matching the pattern, e.g. after the client has detected a gap
+    pub fn snapshot(&self, subscription: &mut Subscription, pattern: EventPattern) -> tokio::io::Result<Vec<ContextEvent>> {
+        let events = self.resolve_pattern(&pattern)?;
+
+        let last_values: Vec<ContextEvent> = events.iter()
+            .filter(|event| subscription.contains(event))
+            .filter_map(|event| self.event_handler.last_value(event))
+            .collect();
+
+        for value in &last_values {
+            subscription.mark_sent(value);
+        }
+
+        Ok(last_values)
     }
 
-    pub async fn update(&mut self) -> tokio::io::Result<()> {
-        for (_context_name, context) in &mut self.contexts {
-            // TODO figure out how to run these updates concurrently           
-            context.context.update().await?;
-        };
+    pub fn unsubscribe(&self, subscription: &mut Subscription, pattern: EventPattern) -> tokio::io::Result<()> {
+        let events = self.resolve_pattern(&pattern)?;
+
+        // NOTE every matching subscription has to be removed, so the iterator can't short-circuit
+        let removed_count = events.iter().filter(|event| subscription.remove_event(event)).count();
+
+        if removed_count == 0 {
+            return Err(std::io::Error::new(ErrorKind::NotFound, format!("Client isn't subscribed to {pattern}")));
+        }
 
         Ok(())
     }
+
+    // Current values of the events without subscribing: { "<context name>/<event name>": <value> }.
+    // Events which haven't been triggered yet have null values, contexts aren't asked to trigger them
+    fn query(&self, peer: &Peer, patterns: &[EventPattern]) -> tokio::io::Result<Option<Value>> {
+        let mut values = serde_json::Map::new();
+
+        for pattern in patterns {
+            for event in self.readable_events(peer, pattern)? {
+                let value = self.event_handler.last_value(&event).map(|value| value.event.value_to_json());
+
+                values.insert(event.to_string(), value.unwrap_or(Value::Null));
+            }
+        }
+
+        Ok(Some(Value::Object(values)))
+    }
+
+    fn history(&self, peer: &Peer, query: &HistoryQuery) -> tokio::io::Result<Option<Value>> {
+        if !self.access.read().unwrap().can_read(peer, &query.event.context) {
+            return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("Client {peer} isn't allowed to read {}", query.event)));
+        }
+
+        Ok(Some(serde_json::to_value(self.event_handler.history().query(query)?)?))
+    }
+
+    // Descriptions of the running contexts, which the client is allowed to read
+    fn describe(&self, peer: &Peer) -> tokio::io::Result<Option<Value>> {
+        let access = self.access.read().unwrap();
+
+        let mut descriptions: BTreeMap<String, ContextDescription> = self.contexts.read().unwrap().iter()
+            .filter(|(context_id, _)| access.can_read(peer, context_id))
+            .map(|(context_id, context)| (context_id.to_string(), context.description.clone()))
+            .collect();
+
+        if access.can_read(peer, &ContextKind::Rsbar.into()) {
+            descriptions.insert(ContextKind::Rsbar.to_string(), builtin_description());
+        }
+
+        Ok(Some(serde_json::to_value(descriptions)?))
+    }
+
+    // Subscribers count of each event, which the client is allowed to read
+    fn subscriptions(&self, peer: &Peer) -> tokio::io::Result<Option<Value>> {
+        let access = self.access.read().unwrap();
+
+        let subscriptions = self.event_handler.subscriptions().into_iter()
+            .filter(|(event, _)| access.can_read(peer, &event.context))
+            .map(|(event, count)| (event.to_string(), Value::from(count)))
+            .collect();
+
+        Ok(Some(Value::Object(subscriptions)))
+    }
+
+    // Events matching the pattern, which the client is allowed to read
+    fn readable_events(&self, peer: &Peer, pattern: &EventPattern) -> tokio::io::Result<Vec<EventAddress>> {
+        let access = self.access.read().unwrap();
+
+        let events: Vec<EventAddress> = self.resolve_pattern(pattern)?.into_iter()
+            .filter(|event| access.can_read(peer, &event.context))
+            .collect();
+
+        if events.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("Client {peer} isn't allowed to read {pattern}")));
+        }
+
+        Ok(events)
+    }
+
+    // Events matching the pattern, which are emitted by the added (or replayed) contexts or by the daemon itself
+    fn resolve_pattern(&self, pattern: &EventPattern) -> tokio::io::Result<Vec<EventAddress>> {
+        let builtin_context   = ContextId::from(ContextKind::Rsbar);
+        let replayed_contexts = self.replay.as_ref().map(ReplayState::contexts).unwrap_or_default();
+
+        let events: Vec<EventAddress> = self.contexts.read().unwrap().keys().chain(replayed_contexts).chain([&builtin_context])
+            .flat_map(|context_id| {
+                EventKind::ALL.iter()
+                    .filter(|kind| kind.context() == context_id.kind())
+                    .map(|kind| EventAddress::new(context_id.clone(), *kind))
+            })
+            .filter(|event| pattern.matches(event))
+            .collect();
+
+        if events.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, format!("No events match {pattern}")));
+        }
+
+        Ok(events)
+    }
+
+    fn context_handle(&self, context_id: &ContextId) -> Option<ContextHandle> {
+        self.contexts.read().unwrap().get(context_id).map(|context| context.handle.clone())
+    }
+
+    // Procedures of the builtin "rsbar" context
+    async fn builtin_call(&self, call: Call) -> tokio::io::Result<Option<Value>> {
+        match call {
+            Call::Reload => {
+                let report = self.reload().await;
+
+                match report.error {
+                    Some(error) => Err(std::io::Error::new(ErrorKind::InvalidData, error)),
+                    None        => Ok(Some(serde_json::to_value(report)?)),
+                }
+            },
+            _ => Err(std::io::Error::new(ErrorKind::Unsupported, format!("Bad procedure value for rsbar context: {}", call.procedure()))),
+        }
+    }
 }
 
-fn split_request(request: &str, right_parts_count: usize) -> tokio::io::Result<Vec<&str>> {
-    let request_trimmed = request.trim();
-    
-    let request_parts: Vec<&str> = request_trimmed.split('/').collect();
-    let parts_count = request_parts.len();
+// NOTE status is published only if it's changed (e.g. a client is connected or some events were dropped)
+async fn publish_subscribers(event_handler: Arc<EventHandler>) {
+    let mut publish_interval = interval(Duration::from_millis(SUBSCRIBERS_STATUS_INTERVAL));
 
-    if parts_count != right_parts_count {
-        return Err(std::io::Error::new(ErrorKind::Other, format!("Invalid request parts count: {request_trimmed}")));
+    loop {
+        publish_interval.tick().await;
+        event_handler.trigger_changed_event(Event::Subscribers(event_handler.subscribers()).into());
     }
+}
+
+fn builtin_description() -> ContextDescription {
+    let object = || Some(ValueDescription::new(ValueType::Object));
 
-    Ok(request_parts)
+    ContextDescription::new("Daemon itself", vec![
+        EventDescription::new(EventKind::ContextStatus, "State of each running context"),
+        EventDescription::new(EventKind::Reload, "Contexts affected by the config reload"),
+        EventDescription::new(EventKind::Shutdown, "Daemon is stopping, the connection is closed after this event"),
+        EventDescription::new(EventKind::Subscribers, "Queue policy and dropped events count of each subscriber"),
+    ], vec![
+        ProcedureDescription { result: object(), ..ProcedureDescription::new("subscriptions", None, "Returns the subscribers count of each event") },
+        ProcedureDescription { result: object(), ..ProcedureDescription::new("reload", None, "Reloads the daemon config and returns the reload report") },
+        ProcedureDescription { result: object(), ..ProcedureDescription::new("describe", None, "Returns descriptions of the running contexts") },
+        ProcedureDescription {
+            result: object(),
+            ..ProcedureDescription::new("query", Some(ValueDescription::new(ValueType::String)), "Returns the current values of the events matching the patterns (separated by spaces)")
+        },
+        ProcedureDescription {
+            result: object(),
+            ..ProcedureDescription::new("history", object(), "Returns the recorded values of an event within the time range, optionally averaged over the steps")
+        },
+    ])
 }
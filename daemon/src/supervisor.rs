@@ -0,0 +1,255 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use log::{error, info};
+use rsbar_protocol::{Call, ContextId, ContextState, ContextStatus, Event, EventKind};
+use serde_json::Value;
+use tokio::{sync::{mpsc, oneshot}, task::JoinHandle, time::{self, Interval, MissedTickBehavior}};
+
+use crate::rsbar_context::{EventHandler, RsbarContext, UpdateSchedule};
+
+const MIN_RESTART_DELAY: u64 = 1000;
+const MAX_RESTART_DELAY: u64 = 60000;
+
+const INBOX_SIZE: usize = 32;
+
+// Runs each context as an independent task, which owns the context and handles commands from its inbox.
+// Failing context is marked as degraded and restarted with exponential backoff, while the others keep running.
+// Context states are published with the "rsbar/contextStatus" event
+pub struct Supervisor {
+    status:        Mutex<ContextStatus>,
+    event_handler: Arc<EventHandler>,
+    tasks:         Mutex<HashMap<ContextId, SupervisedTask>>,
+}
+
+struct SupervisedTask {
+    task: JoinHandle<()>,
+    stop: oneshot::Sender<()>,
+}
+
+enum ContextCommand {
+    Call(Call, oneshot::Sender<tokio::io::Result<Option<Value>>>),
+    ForceEvents,
+}
+
+// Inbox of the context task
+#[derive(Clone)]
+pub struct ContextHandle {
+    inbox: mpsc::Sender<ContextCommand>,
+}
+
+impl Supervisor {
+    pub fn new(event_handler: Arc<EventHandler>) -> Self {
+        Supervisor {
+            status: Mutex::new(ContextStatus::default()),
+            tasks:  Mutex::new(HashMap::new()),
+            event_handler,
+        }
+    }
+
+    pub fn spawn(self: &Arc<Self>, context_id: &ContextId, context: RsbarContext) -> ContextHandle {
+        let (inbox, inbox_rx) = mpsc::channel(INBOX_SIZE);
+        let context_name = context_id.to_string();
+
+        self.set_state(&context_name, ContextState::Starting);
+
+        let supervise = self.clone().supervise(context_id.clone(), context, inbox_rx);
+        let (stop, stop_rx) = oneshot::channel();
+
+        // NOTE context is dropped at the current await point, so it's able to release its resources
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                _ = supervise => {},
+                _ = stop_rx   => info!("Context {context_name} is stopped"),
+            }
+        });
+
+        self.tasks.lock().unwrap().insert(context_id.clone(), SupervisedTask { task, stop });
+
+        ContextHandle { inbox }
+    }
+
+    // Stops the context task and removes the context from the status
+    pub async fn stop_context(&self, context_id: &ContextId) {
+        let Some(task) = self.tasks.lock().unwrap().remove(context_id) else {
+            return;
+        };
+
+        task.stop_and_wait().await;
+
+        let mut status = self.status.lock().unwrap();
+
+        if status.0.remove(&context_id.to_string()).is_some() {
+            self.event_handler.trigger_event(Event::ContextStatus(status.clone()).into());
+        }
+    }
+
+    // Stops all of the context tasks and waits for them to finish
+    pub async fn stop(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+
+        for task in tasks.into_values() {
+            task.stop_and_wait().await;
+        }
+    }
+
+    fn set_state(&self, context_name: &str, state: ContextState) {
+        let mut status = self.status.lock().unwrap();
+
+        if status.0.get(context_name) == Some(&state) {
+            return;
+        }
+
+        status.0.insert(context_name.to_string(), state);
+
+        self.event_handler.trigger_event(Event::ContextStatus(status.clone()).into());
+    }
+
+    async fn supervise(self: Arc<Self>, context_id: ContextId, mut context: RsbarContext, mut inbox: mpsc::Receiver<ContextCommand>) {
+        let context_name = context_id.to_string();
+
+        // NOTE delay is kept across the update failures, so a context which keeps failing after a successful init backs off too
+        let mut restart_delay = MIN_RESTART_DELAY;
+
+        if !self.start(&context_id, &mut context, &mut inbox, &mut restart_delay).await {
+            return;
+        }
+
+        let mut interval = update_interval(context.context.update_schedule());
+
+        loop {
+            tokio::select! {
+                _ = tick(&mut interval) => {
+                    let Err(error) = context.context.update().await else {
+                        restart_delay = MIN_RESTART_DELAY;
+                        continue;
+                    };
+
+                    error!("Unable to update {context_name} context (restarting in {restart_delay} ms): {error}");
+
+                    if !self.wait_restart(&context_name, &error, &mut inbox, &mut restart_delay).await {
+                        return;
+                    }
+
+                    if !self.start(&context_id, &mut context, &mut inbox, &mut restart_delay).await {
+                        return;
+                    }
+
+                    // NOTE interval is created again, so aligned updates keep the wall clock boundaries
+                    interval = update_interval(context.context.update_schedule());
+                },
+                command = inbox.recv() => match command {
+                    Some(ContextCommand::Call(call, reply_tx)) => { let _ = reply_tx.send(context.context.call(call).await); },
+                    Some(ContextCommand::ForceEvents)          => { let _ = context.context.force_events().await; },
+                    None => return,
+                },
+            }
+        }
+    }
+
+    // (Re)initializes the context until it succeeds. Returns false if the inbox is closed
+    async fn start(&self, context_id: &ContextId, context: &mut RsbarContext, inbox: &mut mpsc::Receiver<ContextCommand>, restart_delay: &mut u64) -> bool {
+        let context_name = context_id.to_string();
+
+        loop {
+            let policies = EventKind::ALL.iter()
+                .filter(|kind| kind.context() == context_id.kind())
+                .map(|kind| (*kind, context.context.event_policy(*kind)))
+                .collect();
+
+            let error = match context.context.init(self.event_handler.emitter(context_id.clone(), policies)).await {
+                Ok(()) => break,
+                Err(error) => error,
+            };
+
+            error!("Unable to start {context_name} context (retrying in {restart_delay} ms): {error}");
+
+            if !self.wait_restart(&context_name, &error, inbox, restart_delay).await {
+                return false;
+            }
+        }
+
+        info!("Context {context_name} is running");
+
+        self.set_state(&context_name, ContextState::Running);
+
+        true
+    }
+
+    // Marks the context as degraded and waits for the restart delay, which is doubled afterwards.
+    // Commands received in the meantime are rejected. Returns false if the inbox is closed
+    async fn wait_restart(&self, context_name: &str, error: &std::io::Error, inbox: &mut mpsc::Receiver<ContextCommand>, restart_delay: &mut u64) -> bool {
+        self.set_state(context_name, ContextState::Degraded { error: error.to_string() });
+
+        let restart = time::sleep(Duration::from_millis(*restart_delay));
+        tokio::pin!(restart);
+
+        loop {
+            tokio::select! {
+                _ = &mut restart => break,
+                command = inbox.recv() => match command {
+                    Some(ContextCommand::Call(_, reply_tx)) => {
+                        let _ = reply_tx.send(Err(std::io::Error::other(format!("Context {context_name} is not running: {error}"))));
+                    },
+                    Some(ContextCommand::ForceEvents) => {},
+                    None => return false,
+                },
+            }
+        }
+
+        *restart_delay = (*restart_delay * 2).min(MAX_RESTART_DELAY);
+
+        true
+    }
+}
+
+impl SupervisedTask {
+    async fn stop_and_wait(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+impl ContextHandle {
+    pub async fn call(&self, call: Call) -> tokio::io::Result<Option<Value>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+
+        if self.inbox.send(ContextCommand::Call(call, reply_tx)).await.is_err() {
+            return Err(std::io::Error::other("Context task is stopped"));
+        }
+
+        reply_rx.await.unwrap_or_else(|_| Err(std::io::Error::other("Context task is stopped")))
+    }
+
+    // Broadcasts current values of the context events
+    pub async fn force_events(&self) {
+        let _ = self.inbox.send(ContextCommand::ForceEvents).await;
+    }
+}
+
+fn update_interval(schedule: UpdateSchedule) -> Option<Interval> {
+    let mut interval = match schedule {
+        UpdateSchedule::Interval(period) => time::interval(period),
+        UpdateSchedule::Aligned(period)  => time::interval_at(time::Instant::now() + time_to_boundary(period), period),
+        UpdateSchedule::OnDemand         => return None,
+    };
+
+    // Slow update shouldn't be followed by a burst of the missed ones
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    Some(interval)
+}
+
+// Never completes for the contexts without update schedule
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => { interval.tick().await; },
+        None           => std::future::pending().await,
+    }
+}
+
+fn time_to_boundary(period: Duration) -> Duration {
+    let period_millis = period.as_millis().max(1);
+    let now_millis    = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+    Duration::from_millis((period_millis - now_millis % period_millis) as u64)
+}
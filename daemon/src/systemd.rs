@@ -0,0 +1,160 @@
+use std::{ffi::OsStr, os::{fd::{FromRawFd, OwnedFd}, linux::net::SocketAddrExt, unix::{ffi::OsStrExt, net::{SocketAddr, UnixDatagram}}}, path::Path};
+
+use log::warn;
+use tokio::net::UnixListener;
+
+// First file descriptor passed by systemd (SD_LISTEN_FDS_START)
+const LISTEN_FDS_START: i32 = 3;
+
+// Listeners passed with systemd socket activation
+#[derive(Default)]
+pub struct ActivatedSockets {
+    pub call:  Option<UnixListener>,
+    pub event: Option<UnixListener>,
+}
+
+// Sends a state update (e.g. "READY=1") to the service manager, if the daemon is run by systemd with Type=notify
+pub fn notify(state: &str) {
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    if let Err(error) = send_notification(&socket_path, state) {
+        warn!("Unable to notify the service manager ({state}): {error}");
+    }
+}
+
+fn send_notification(socket_path: &OsStr, state: &str) -> std::io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+
+    // NOTE names of abstract sockets start with "@"
+    let address = match socket_path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None       => SocketAddr::from_pathname(Path::new(socket_path))?,
+    };
+
+    socket.send_to_addr(state.as_bytes(), &address)?;
+
+    Ok(())
+}
+
+// Sockets are matched by their names (FileDescriptorName=call / event),
+// unnamed sockets are expected in the order of ListenStream= entries: call socket first.
+// NOTE LISTEN_* variables are left as is, child processes ignore them because of LISTEN_PID
+pub fn listen_fds() -> std::io::Result<ActivatedSockets> {
+    let mut sockets = ActivatedSockets::default();
+
+    let passed_fds = passed_fds(std::env::var("LISTEN_PID").ok(), std::env::var("LISTEN_FDS").ok(), std::env::var("LISTEN_FDNAMES").ok(), std::process::id());
+
+    for (index, (fd, fd_name)) in passed_fds.into_iter().enumerate() {
+        // Passed descriptors are inherited by the spawned processes otherwise
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let listener = std::os::unix::net::UnixListener::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        listener.set_nonblocking(true)?;
+
+        let slot = match (fd_name.as_deref(), index) {
+            (Some("call"), _)  => &mut sockets.call,
+            (Some("event"), _) => &mut sockets.event,
+            (_, 0)             => &mut sockets.call,
+            (_, 1)             => &mut sockets.event,
+            _ => {
+                warn!("Unexpected socket passed by systemd (fd {fd})");
+                continue;
+            },
+        };
+
+        *slot = Some(UnixListener::from_std(listener)?);
+    }
+
+    Ok(sockets)
+}
+
+// Descriptors passed to the process with their names. They are meant for another process unless LISTEN_PID matches
+fn passed_fds(listen_pid: Option<String>, listen_fds: Option<String>, fd_names: Option<String>, pid: u32) -> Vec<(i32, Option<String>)> {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse::<u32>().ok()) != Some(pid) {
+        return Vec::new();
+    }
+
+    let fd_count = listen_fds.and_then(|count| count.parse::<i32>().ok()).unwrap_or(0);
+    let fd_names = fd_names.unwrap_or_default();
+    let fd_names: Vec<&str> = fd_names.split(':').collect();
+
+    // NOTE names are positional, an empty name means an unnamed socket
+    (0..fd_count)
+        .map(|index| (LISTEN_FDS_START + index, fd_names.get(index as usize).filter(|name| !name.is_empty()).map(|name| name.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 64];
+        let length = socket.recv(&mut buffer).unwrap();
+
+        String::from_utf8_lossy(&buffer[..length]).to_string()
+    }
+
+    // NOTE it's the only test which sets NOTIFY_SOCKET, so the tests can run in parallel
+    #[test]
+    fn states_are_sent_to_the_notify_socket() {
+        let socket_path = std::env::temp_dir().join(format!("rsbar-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let socket = UnixDatagram::bind(&socket_path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        std::env::set_var("NOTIFY_SOCKET", &socket_path);
+
+        notify("READY=1");
+        notify("STOPPING=1");
+
+        assert_eq!(receive(&socket), "READY=1");
+        assert_eq!(receive(&socket), "STOPPING=1");
+
+        let _ = std::fs::remove_file(&socket_path);
+    }
+
+    #[test]
+    fn abstract_socket_is_supported() {
+        let name   = format!("rsbar-notify-{}", std::process::id());
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes()).unwrap()).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        send_notification(OsStr::new(&format!("@{name}")), "READY=1").unwrap();
+
+        assert_eq!(receive(&socket), "READY=1");
+    }
+
+    #[test]
+    fn passed_fds_are_named() {
+        let fds = passed_fds(Some("42".into()), Some("2".into()), Some("event:call".into()), 42);
+
+        assert_eq!(fds, vec![(3, Some("event".to_string())), (4, Some("call".to_string()))]);
+    }
+
+    #[test]
+    fn unnamed_fds_are_kept_in_order() {
+        assert_eq!(passed_fds(Some("42".into()), Some("2".into()), None, 42), vec![(3, None), (4, None)]);
+        assert_eq!(passed_fds(Some("42".into()), Some("2".into()), Some(":event".into()), 42), vec![(3, None), (4, Some("event".to_string()))]);
+    }
+
+    #[test]
+    fn fds_of_another_process_are_ignored() {
+        assert!(passed_fds(Some("41".into()), Some("2".into()), None, 42).is_empty());
+        assert!(passed_fds(None, Some("2".into()), None, 42).is_empty());
+        assert!(passed_fds(Some("bad".into()), Some("2".into()), None, 42).is_empty());
+    }
+
+    #[test]
+    fn bad_fd_count_means_no_fds() {
+        assert!(passed_fds(Some("42".into()), Some("many".into()), None, 42).is_empty());
+        assert!(passed_fds(Some("42".into()), None, None, 42).is_empty());
+    }
+}
@@ -1,23 +1,38 @@
-use std::{io::ErrorKind, sync::Arc};
+use std::{fmt::Write, io::ErrorKind, time::Duration};
 
 use async_trait::async_trait;
-use chrono::{DateTime, Local};
-use tokio::sync::Mutex;
+use chrono::{format::{Item, StrftimeItems}, DateTime, Local};
+use rsbar_protocol::{Call, ContextDescription, Event, EventDescription, EventKind};
+use serde::{de, Deserialize, Deserializer};
 
-use crate::rsbar_context::{EventHandler, RsbarContext, RsbarContextContent};
+use crate::rsbar_context::{EventEmitter, RsbarContextContent, UpdateSchedule};
 
 const TIME_FORMAT: &str = "%H\n%M";
+const TIME_UPDATE_INTERVAL: u64 = 1000;
+
+// [contexts.time] section of the daemon config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeSettings {
+    pub enabled:     bool,
+    // strftime-like format (see chrono::format::strftime)
+    #[serde(deserialize_with = "time_format")]
+    format:          String,
+    #[serde(deserialize_with = "crate::config::interval")]
+    update_interval: u64,
+}
 
 pub struct TimeContext {
     time:          DateTime<Local>,
-    event_handler: Option<Arc<Mutex<EventHandler>>>,
+    settings:      TimeSettings,
+    event_emitter: Option<EventEmitter>,
 }
 
 #[async_trait]
 impl RsbarContextContent for TimeContext {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()> {
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()> {
         self.time = Local::now();
-        self.event_handler = Some(event_handler);
+        self.event_emitter = Some(event_emitter);
 
         self.update().await?;
 
@@ -32,29 +47,65 @@ impl RsbarContextContent for TimeContext {
         Ok(())
     }
 
-    async fn call(&mut self, _procedure: &str, _args: &str) -> tokio::io::Result<()> {
-        Err(std::io::Error::new(ErrorKind::NotFound, "Time context does not support calls"))
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::Aligned(Duration::from_millis(self.settings.update_interval))
+    }
+
+    fn describe(&self) -> ContextDescription {
+        ContextDescription::new("Local time", vec![
+            EventDescription::new(EventKind::Time, &format!("Current time in the {:?} format", self.settings.format)),
+        ], Vec::new())
+    }
+
+    async fn call(&mut self, _call: Call) -> tokio::io::Result<Option<serde_json::Value>> {
+        Err(std::io::Error::new(ErrorKind::Unsupported, "Time context does not support calls"))
     }
 
     async fn force_events(&mut self) -> tokio::io::Result<()> {
-        if self.event_handler.is_none() {
-            return Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found"));
+        if self.event_emitter.is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "Event emitter was not found"));
         }
 
-        self.event_handler.as_mut().unwrap().lock().await
-            .trigger_event("time/time", &self.time.format(TIME_FORMAT).to_string()).await;
+        // NOTE formatting fails (instead of panicking) if the configured format is invalid
+        let mut time = String::new();
+
+        if write!(time, "{}", self.time.format(&self.settings.format)).is_err() {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Bad time format: {}", self.settings.format)));
+        }
+
+        self.event_emitter.as_ref().unwrap().trigger_event(Event::Time(time));
 
         Ok(())
     }
 }
 
 impl TimeContext {
-    pub fn new() -> (String, RsbarContext) {
-        let new_context = Box::new(TimeContext {
-            time: Local::now(),
-            event_handler: None,
-        });
+    pub fn new(settings: TimeSettings) -> Self {
+        TimeContext {
+            time:          Local::now(),
+            settings,
+            event_emitter: None,
+        }
+    }
+}
+
+impl Default for TimeSettings {
+    fn default() -> Self {
+        TimeSettings {
+            enabled:         true,
+            format:          TIME_FORMAT.to_string(),
+            update_interval: TIME_UPDATE_INTERVAL,
+        }
+    }
+}
 
-        ("time".to_string(), RsbarContext::new(new_context))
+// Bad format is reported on config load, the context would keep failing otherwise
+fn time_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let format = String::deserialize(deserializer)?;
+
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return Err(de::Error::custom(format!("Bad time format: {format}")));
     }
+
+    Ok(format)
 }
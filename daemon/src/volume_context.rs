@@ -1,24 +1,44 @@
-use core::str;
-use std::{io::ErrorKind, process::Command, sync::Arc};
+use std::{io::ErrorKind, time::Duration};
 
 use async_trait::async_trait;
-use tokio::sync::Mutex;
+use rsbar_protocol::{Call, ContextDescription, Event, EventDescription, EventKind, ProcedureDescription, ValueDescription};
+use serde::Deserialize;
+use tokio::process::Command;
 
-use crate::rsbar_context::{EventHandler, RsbarContext, RsbarContextContent};
+use crate::rsbar_context::{EventEmitter, EventPolicy, RsbarContextContent, UpdateSchedule};
 
 const MAX_VOLUME: u32 = 100;
 const MIN_VOLUME: u32 = 0;
 
+const DEFAULT_NODE: &str = "@DEFAULT_AUDIO_SINK@";
+const VOLUME_UPDATE_INTERVAL: u64 = 1000;
+
+// Dragging a volume slider produces a call per step, so volume events are sent at most once per this interval
+const VOLUME_EVENT_INTERVAL: u64 = 50;
+
+// [contexts.volume] section of the daemon config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeSettings {
+    pub enabled:     bool,
+    // wpctl node id or name (e.g. "@DEFAULT_AUDIO_SOURCE@" for the microphone)
+    node:            String,
+    max_volume:      u32,
+    #[serde(deserialize_with = "crate::config::interval")]
+    update_interval: u64,
+}
+
 pub struct VolumeContext {
     volume:        u32,
     is_muted:      bool,
-    event_handler: Option<Arc<Mutex<EventHandler>>>,
+    settings:      VolumeSettings,
+    event_emitter: Option<EventEmitter>,
 }
 
 #[async_trait]
 impl RsbarContextContent for VolumeContext {
-    async fn init(&mut self, event_handler: Arc<Mutex<EventHandler>>) -> tokio::io::Result<()>{
-        self.event_handler = Some(event_handler);
+    async fn init(&mut self, event_emitter: EventEmitter) -> tokio::io::Result<()>{
+        self.event_emitter = Some(event_emitter);
 
         self.update().await?;
 
@@ -26,7 +46,7 @@ impl RsbarContextContent for VolumeContext {
     }
 
     async fn update(&mut self) -> tokio::io::Result<()> {
-        let output = Command::new("wpctl").arg("get-volume").arg("@DEFAULT_AUDIO_SINK@").output()?;
+        let output = Command::new("wpctl").arg("get-volume").arg(&self.settings.node).output().await?;
 
         let result_string = String::from_utf8_lossy(&output.stdout);
         let mut sound_value_chars;
@@ -53,67 +73,101 @@ impl RsbarContextContent for VolumeContext {
         Ok(())
     }
 
-    async fn call(&mut self, procedure: &str, args: &str) -> tokio::io::Result<()> {
-        match procedure {
-            "setVolume"  => self.set_volume(args)?,
-            "toggleMute" => self.toggle_muted(args)?,
-            _ => return Err(std::io::Error::new(ErrorKind::NotFound, format!("Bad procedure value for volume context: {procedure}"))),
+    fn update_schedule(&self) -> UpdateSchedule {
+        UpdateSchedule::Interval(Duration::from_millis(self.settings.update_interval))
+    }
+
+    fn event_policy(&self, kind: EventKind) -> EventPolicy {
+        match kind {
+            EventKind::Volume => EventPolicy::Throttle(Duration::from_millis(VOLUME_EVENT_INTERVAL)),
+            _                 => EventPolicy::OnChange,
+        }
+    }
+
+    fn describe(&self) -> ContextDescription {
+        let volume_range = ValueDescription::integer(MIN_VOLUME.into(), self.settings.max_volume.into());
+
+        ContextDescription::new(&format!("Volume of the {} wpctl node", self.settings.node), vec![
+            EventDescription { value: volume_range.clone(), ..EventDescription::new(EventKind::Volume, "Volume in percents") },
+            EventDescription::new(EventKind::VolumeMuted, "Whether the node is muted"),
+        ], vec![
+            ProcedureDescription::new("setVolume", Some(volume_range), "Sets the volume in percents"),
+            ProcedureDescription::new("toggleMute", None, "Mutes or unmutes the node"),
+        ])
+    }
+
+    async fn call(&mut self, call: Call) -> tokio::io::Result<Option<serde_json::Value>> {
+        match call {
+            Call::SetVolume(volume) => self.set_volume(volume).await?,
+            Call::ToggleMute        => self.toggle_muted().await?,
+            _ => return Err(std::io::Error::new(ErrorKind::Unsupported, format!("Bad procedure value for volume context: {}", call.procedure()))),
         };
 
         self.force_events().await?; // TODO use update instead of force_events?
 
-        Ok(())
+        Ok(None)
     }
 
     async fn force_events(&mut self) -> tokio::io::Result<()> {
-        if self.event_handler.is_none() {
-            return Err(std::io::Error::new(ErrorKind::NotFound, "Event handler was not found"));
+        if self.event_emitter.is_none() {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "Event emitter was not found"));
         }
 
-        let events = self.event_handler.as_mut().unwrap().lock().await;
+        let events = self.event_emitter.as_ref().unwrap();
 
-        events.trigger_event("volume/volume",  &self.volume.to_string()).await;
-        events.trigger_event("volume/isMuted", &self.is_muted.to_string()).await;
+        events.trigger_event(Event::Volume(self.volume));
+        events.trigger_event(Event::VolumeMuted(self.is_muted));
 
         Ok(())
     }
 }
 
 impl VolumeContext {
-    pub fn new() -> (String, RsbarContext) {
-        let new_context = Box::new(VolumeContext {
+    pub fn new(settings: VolumeSettings) -> Self {
+        VolumeContext {
             volume:        0,
             is_muted:      false,
-            event_handler: None,
-        });
-
-        ("volume".to_string(), RsbarContext::new(new_context))
+            settings,
+            event_emitter: None,
+        }
     }
 
-    fn set_volume(&mut self, args: &str) -> tokio::io::Result<()> {
-        let parse_result = args.parse::<u32>();
-
-        if parse_result.is_err() {
-            return Err(std::io::Error::new(ErrorKind::Other, format!("Bad volume value: {args}")));
+    async fn set_volume(&mut self, value: u32) -> tokio::io::Result<()> {
+        if !(MIN_VOLUME..=self.settings.max_volume).contains(&value) {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Volume value is out of range: {value}")));
         }
 
-        let value = parse_result.unwrap();
-
-        if value < MIN_VOLUME || value > MAX_VOLUME {
-            return Err(std::io::Error::new(ErrorKind::Other, format!("Volume value is out of range: {args}")));
-        }
+        check_status(Command::new("wpctl").arg("set-volume").arg(&self.settings.node).arg(format!("{}%", value)).status().await?)?;
 
         self.volume = value;   
-
-        Command::new("wpctl").arg("set-volume").arg("@DEFAULT_AUDIO_SINK@").arg(format!("{}%", value)).status()?;
         
         Ok(())
     }
 
-    fn toggle_muted(&mut self, _args: &str) -> tokio::io::Result<()> {
+    async fn toggle_muted(&mut self) -> tokio::io::Result<()> {
+        check_status(Command::new("wpctl").arg("set-mute").arg(&self.settings.node).arg("toggle").status().await?)?;
+
         self.is_muted = !self.is_muted;
-        Command::new("wpctl").arg("set-mute").arg("@DEFAULT_AUDIO_SINK@").arg("toggle").status()?;
 
         Ok(())
     }
 }
+
+impl Default for VolumeSettings {
+    fn default() -> Self {
+        VolumeSettings {
+            enabled:         true,
+            node:            DEFAULT_NODE.to_string(),
+            max_volume:      MAX_VOLUME,
+            update_interval: VOLUME_UPDATE_INTERVAL,
+        }
+    }
+}
+
+fn check_status(status: std::process::ExitStatus) -> tokio::io::Result<()> {
+    if !status.success() {
+        return Err(std::io::Error::other(format!("wpctl failed: {status}")));
+    }
+
+    Ok(())
+}
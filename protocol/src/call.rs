@@ -0,0 +1,242 @@
+use std::io::ErrorKind;
+
+use serde_json::Value;
+
+use crate::{context::{ContextId, ContextKind}, history::HistoryQuery, pattern::EventPattern, value::WireValue};
+
+// Call is referred on the wire as "<context name>/<procedure name>"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    SetVolume(u32),
+    ToggleMute,
+    SetBrightness(u32),
+    SetWorkspace(i32),
+    // Returns subscribers count of each event: { "<context name>/<event name>": <count> }
+    Subscriptions,
+    // Re-reads the daemon config, returns ReloadReport
+    Reload,
+    // Returns descriptions of the running contexts: { "<context name>": ContextDescription }
+    Describe,
+    // Returns the current values of the matching events: { "<context name>/<event name>": <value> }
+    Query(Vec<EventPattern>),
+    // Returns the recorded values of an integer event: [HistorySample]
+    History(HistoryQuery),
+}
+
+// Call addressed to a context instance: "<context id>/<procedure name>"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextCall {
+    pub context: ContextId,
+    pub call:    Call,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    UnknownContext,
+    UnknownProcedure,
+    InvalidArgument,
+    PermissionDenied,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallReply {
+    Ok(Option<Value>),
+    Error(ErrorCode, String),
+}
+
+pub(crate) enum CallArgs {
+    Text(String),
+    Json(Value),
+}
+
+impl Call {
+    pub fn context(&self) -> ContextKind {
+        match self {
+            Call::SetVolume(_)     => ContextKind::Volume,
+            Call::ToggleMute       => ContextKind::Volume,
+            Call::SetBrightness(_) => ContextKind::Brightness,
+            Call::SetWorkspace(_)  => ContextKind::Hyprland,
+            Call::Subscriptions    => ContextKind::Rsbar,
+            Call::Reload           => ContextKind::Rsbar,
+            Call::Describe         => ContextKind::Rsbar,
+            Call::Query(_)         => ContextKind::Rsbar,
+            Call::History(_)       => ContextKind::Rsbar,
+        }
+    }
+
+    pub fn procedure(&self) -> &'static str {
+        match self {
+            Call::SetVolume(_)     => "setVolume",
+            Call::ToggleMute       => "toggleMute",
+            Call::SetBrightness(_) => "setBrightness",
+            Call::SetWorkspace(_)  => "setWorkspace",
+            Call::Subscriptions    => "subscriptions",
+            Call::Reload           => "reload",
+            Call::Describe         => "describe",
+            Call::Query(_)         => "query",
+            Call::History(_)       => "history",
+        }
+    }
+
+    pub fn args_to_text(&self) -> String {
+        match self {
+            Call::SetVolume(volume)         => volume.to_text(),
+            Call::ToggleMute                => String::new(),
+            Call::SetBrightness(brightness) => brightness.to_text(),
+            Call::SetWorkspace(workspace)   => workspace.to_text(),
+            Call::Subscriptions             => String::new(),
+            Call::Reload                    => String::new(),
+            Call::Describe                  => String::new(),
+            Call::Query(patterns)           => patterns.to_text(),
+            Call::History(query)            => query.to_text(),
+        }
+    }
+
+    pub fn args_to_json(&self) -> Value {
+        match self {
+            Call::SetVolume(volume)         => volume.to_json(),
+            Call::ToggleMute                => Value::Null,
+            Call::SetBrightness(brightness) => brightness.to_json(),
+            Call::SetWorkspace(workspace)   => workspace.to_json(),
+            Call::Subscriptions             => Value::Null,
+            Call::Reload                    => Value::Null,
+            Call::Describe                  => Value::Null,
+            Call::Query(patterns)           => patterns.to_json(),
+            Call::History(query)            => query.to_json(),
+        }
+    }
+
+    // Unknown context is reported with ErrorKind::NotFound, unknown procedure with ErrorKind::Unsupported
+    // and bad arguments with ErrorKind::InvalidInput
+    pub(crate) fn parse(context_name: &str, procedure: &str, args: CallArgs) -> std::io::Result<ContextCall> {
+        let context = context_name.parse::<ContextId>()?;
+
+        let call = match (context.kind(), procedure) {
+            (ContextKind::Volume,     "setVolume")     => Call::SetVolume(args.parse()?),
+            (ContextKind::Volume,     "toggleMute")    => Call::ToggleMute,
+            (ContextKind::Brightness, "setBrightness") => Call::SetBrightness(args.parse()?),
+            (ContextKind::Hyprland,   "setWorkspace")  => Call::SetWorkspace(args.parse()?),
+            (ContextKind::Rsbar,      "subscriptions") => Call::Subscriptions,
+            (ContextKind::Rsbar,      "reload")        => Call::Reload,
+            (ContextKind::Rsbar,      "describe")      => Call::Describe,
+            (ContextKind::Rsbar,      "query")         => Call::Query(args.parse()?),
+            (ContextKind::Rsbar,      "history")       => Call::History(args.parse()?),
+            _ => return Err(std::io::Error::new(ErrorKind::Unsupported, format!("Bad procedure value for {context_name} context: {procedure}"))),
+        };
+
+        Ok(ContextCall { context, call })
+    }
+}
+
+impl std::fmt::Display for Call {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}/{}", self.context(), self.procedure(), self.args_to_text())
+    }
+}
+
+// Call of the default context instance
+impl From<Call> for ContextCall {
+    fn from(call: Call) -> Self {
+        ContextCall {
+            context: call.context().into(),
+            call,
+        }
+    }
+}
+
+impl std::fmt::Display for ContextCall {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}/{}", self.context, self.call.procedure(), self.call.args_to_text())
+    }
+}
+
+impl CallArgs {
+    fn parse<T: WireValue>(self) -> std::io::Result<T> {
+        match self {
+            CallArgs::Text(text) => T::from_text(&text),
+            CallArgs::Json(json) => T::from_json(json),
+        }
+    }
+}
+
+impl CallReply {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        CallReply::Error(code, message.into())
+    }
+
+    // Error occurred while decoding the request
+    pub fn bad_request(error: std::io::Error) -> Self {
+        let code = match error.kind() {
+            ErrorKind::NotFound         => ErrorCode::UnknownContext,
+            ErrorKind::Unsupported      => ErrorCode::UnknownProcedure,
+            ErrorKind::InvalidInput     => ErrorCode::InvalidArgument,
+            ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            _                           => ErrorCode::BadRequest,
+        };
+
+        CallReply::Error(code, error.to_string())
+    }
+}
+
+// Procedure result: bad arguments are expected to be reported with ErrorKind::InvalidInput,
+// unsupported procedures with ErrorKind::Unsupported and denied ones with ErrorKind::PermissionDenied
+impl From<std::io::Result<Option<Value>>> for CallReply {
+    fn from(result: std::io::Result<Option<Value>>) -> Self {
+        match result {
+            Ok(value)  => CallReply::Ok(value),
+            Err(error) => CallReply::Error(error.kind().into(), error.to_string()),
+        }
+    }
+}
+
+impl From<ErrorKind> for ErrorCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::InvalidInput     => ErrorCode::InvalidArgument,
+            ErrorKind::Unsupported      => ErrorCode::UnknownProcedure,
+            ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            _                           => ErrorCode::Failed,
+        }
+    }
+}
+
+impl ErrorCode {
+    const ALL: &'static [ErrorCode] = &[
+        ErrorCode::BadRequest,
+        ErrorCode::UnknownContext,
+        ErrorCode::UnknownProcedure,
+        ErrorCode::InvalidArgument,
+        ErrorCode::PermissionDenied,
+        ErrorCode::Failed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest       => "badRequest",
+            ErrorCode::UnknownContext   => "unknownContext",
+            ErrorCode::UnknownProcedure => "unknownProcedure",
+            ErrorCode::InvalidArgument  => "invalidArgument",
+            ErrorCode::PermissionDenied => "permissionDenied",
+            ErrorCode::Failed           => "failed",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for ErrorCode {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match ErrorCode::ALL.iter().find(|code| code.name() == string) {
+            Some(code) => Ok(*code),
+            None => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Unknown error code: {string}"))),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Deserializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ContextKind {
+    Time,
+    Volume,
+    Brightness,
+    Hyprland,
+    Battery,
+    // Builtin context of the daemon itself
+    Rsbar,
+}
+
+impl ContextKind {
+    pub const ALL: &'static [ContextKind] = &[
+        ContextKind::Time,
+        ContextKind::Volume,
+        ContextKind::Brightness,
+        ContextKind::Hyprland,
+        ContextKind::Battery,
+        ContextKind::Rsbar,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContextKind::Time       => "time",
+            ContextKind::Volume     => "volume",
+            ContextKind::Brightness => "brightness",
+            ContextKind::Hyprland   => "hyprland",
+            ContextKind::Battery    => "battery",
+            ContextKind::Rsbar      => "rsbar",
+        }
+    }
+}
+
+// Context instance, referred on the wire as "<context name>" (the default instance)
+// or "<context name>@<instance name>" (e.g. "battery@BAT1")
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContextId {
+    kind:     ContextKind,
+    instance: Option<String>,
+}
+
+impl ContextId {
+    pub fn new(kind: ContextKind, instance: Option<String>) -> Self {
+        ContextId { kind, instance }
+    }
+
+    pub fn kind(&self) -> ContextKind {
+        self.kind
+    }
+
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+}
+
+impl From<ContextKind> for ContextId {
+    fn from(kind: ContextKind) -> Self {
+        ContextId::new(kind, None)
+    }
+}
+
+impl std::fmt::Display for ContextKind {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for ContextKind {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match ContextKind::ALL.iter().find(|context| context.name() == string) {
+            Some(context) => Ok(*context),
+            None => Err(std::io::Error::new(ErrorKind::NotFound, format!("Unknown context: {string}"))),
+        }
+    }
+}
+
+impl std::fmt::Display for ContextId {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.instance {
+            Some(instance) => write!(formatter, "{}@{instance}", self.kind),
+            None           => write!(formatter, "{}", self.kind),
+        }
+    }
+}
+
+impl std::str::FromStr for ContextId {
+    type Err = std::io::Error;
+
+    // Unknown context kind is reported with ErrorKind::NotFound
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let Some((kind, instance)) = string.split_once('@') else {
+            return Ok(ContextId::new(string.parse()?, None));
+        };
+
+        let is_valid_instance = !instance.is_empty() && instance.chars().all(|char| char.is_alphanumeric() || "-_.".contains(char));
+
+        if !is_valid_instance {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad instance name: {string}")));
+        }
+
+        Ok(ContextId::new(kind.parse()?, Some(instance.to_string())))
+    }
+}
+
+// Context ids are written as strings in the configs, e.g. "battery@BAT1"
+impl<'de> Deserialize<'de> for ContextId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{context::{ContextId, ContextKind}, introspection::ValueDescription, pattern::EventPattern, status::{Backpressure, ContextStatus, ReloadReport, SubscribersStatus}, value::WireValue};
+
+// Each event is declared as: <variant>(<value type>) => <context kind> / "<event name>"
+// Event is referred on the wire as "<context name>/<event name>"
+macro_rules! events {
+    ($($kind:ident($value_type:ty) => $context:ident / $name:literal,)*) => {
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum Event {
+            $($kind($value_type),)*
+        }
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum EventKind {
+            $($kind,)*
+        }
+
+        impl EventKind {
+            pub const ALL: &'static [EventKind] = &[$(EventKind::$kind,)*];
+
+            pub fn context(&self) -> ContextKind {
+                match self {
+                    $(EventKind::$kind => ContextKind::$context,)*
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(EventKind::$kind => $name,)*
+                }
+            }
+
+            pub fn value_description(&self) -> ValueDescription {
+                match self {
+                    $(EventKind::$kind => ValueDescription::of::<$value_type>(),)*
+                }
+            }
+
+            pub fn value_from_text(&self, text: &str) -> std::io::Result<Event> {
+                match self {
+                    $(EventKind::$kind => Ok(Event::$kind(<$value_type>::from_text(text)?)),)*
+                }
+            }
+
+            pub fn value_from_json(&self, value: Value) -> std::io::Result<Event> {
+                match self {
+                    $(EventKind::$kind => Ok(Event::$kind(<$value_type>::from_json(value)?)),)*
+                }
+            }
+        }
+
+        impl Event {
+            pub fn kind(&self) -> EventKind {
+                match self {
+                    $(Event::$kind(_) => EventKind::$kind,)*
+                }
+            }
+
+            pub fn value_to_text(&self) -> String {
+                match self {
+                    $(Event::$kind(value) => value.to_text(),)*
+                }
+            }
+
+            pub fn value_to_json(&self) -> Value {
+                match self {
+                    $(Event::$kind(value) => value.to_json(),)*
+                }
+            }
+        }
+    };
+}
+
+events! {
+    Time(String)                   => Time       / "time",
+    Volume(u32)                    => Volume     / "volume",
+    VolumeMuted(bool)              => Volume     / "isMuted",
+    Brightness(u32)                => Brightness / "brightness",
+    HyprlandWorkspace(i32)         => Hyprland   / "workspace",
+    BatteryCapacity(u32)           => Battery    / "capacity",
+    BatteryStatus(BatteryStatus)   => Battery    / "status",
+    ContextStatus(ContextStatus)   => Rsbar      / "contextStatus",
+    Shutdown(())                   => Rsbar      / "shutdown",
+    Reload(ReloadReport)           => Rsbar      / "reload",
+    Subscribers(SubscribersStatus) => Rsbar      / "subscribers",
+}
+
+// Event of a specific context instance: "<context id>/<event name>"
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventAddress {
+    pub context: ContextId,
+    pub kind:    EventKind,
+}
+
+// Event triggered by a context instance. Events sent by the daemon are stamped (v2 protocol only)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextEvent {
+    pub context: ContextId,
+    pub event:   Event,
+    pub stamp:   Option<EventStamp>,
+}
+
+// Sequence number grows by one with each value of the event (of a context instance),
+// so a gap means missed values. Timestamp is the daemon time in milliseconds since the unix epoch.
+// Values replaced by this one in the subscriber queue (coalesce policy) are counted, such gap isn't a loss
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventStamp {
+    pub seq:       u64,
+    pub timestamp: u64,
+    pub coalesced: u64,
+}
+
+// Request sent by a client to the event socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionRequest {
+    Subscribe(EventPattern),
+    Unsubscribe(EventPattern),
+    // Sends the last values of the subscribed events matching the pattern again
+    Snapshot(EventPattern),
+    // Changes the queue policy of the connection
+    Backpressure(Backpressure),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatteryStatus {
+    Charging,
+    Discharging,
+    Full,
+    NotCharging,
+    Unknown,
+}
+
+impl BatteryStatus {
+    pub const ALL: &'static [BatteryStatus] = &[
+        BatteryStatus::Charging,
+        BatteryStatus::Discharging,
+        BatteryStatus::Full,
+        BatteryStatus::NotCharging,
+        BatteryStatus::Unknown,
+    ];
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}", self.context(), self.name())
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = std::io::Error;
+
+    // Event name format: "<context name>/<event name>"
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (context_name, event_name) = match string.trim().split_once('/') {
+            Some(split) => split,
+            None => return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad event name format: {string}"))),
+        };
+
+        let context = context_name.parse::<ContextKind>()?;
+
+        match EventKind::ALL.iter().find(|kind| kind.context() == context && kind.name() == event_name) {
+            Some(kind) => Ok(*kind),
+            None => Err(std::io::Error::new(ErrorKind::NotFound, format!("Unknown event: {string}"))),
+        }
+    }
+}
+
+impl EventAddress {
+    pub fn new(context: ContextId, kind: EventKind) -> Self {
+        EventAddress { context, kind }
+    }
+
+    // Unknown context is reported with ErrorKind::NotFound
+    pub fn parse(context_name: &str, event_name: &str) -> std::io::Result<Self> {
+        let context = context_name.parse::<ContextId>()?;
+
+        match EventKind::ALL.iter().find(|kind| kind.context() == context.kind() && kind.name() == event_name) {
+            Some(kind) => Ok(EventAddress::new(context, *kind)),
+            None => Err(std::io::Error::new(ErrorKind::NotFound, format!("Unknown event: {context_name}/{event_name}"))),
+        }
+    }
+}
+
+// Event of the default context instance
+impl From<EventKind> for EventAddress {
+    fn from(kind: EventKind) -> Self {
+        EventAddress::new(kind.context().into(), kind)
+    }
+}
+
+impl std::fmt::Display for EventAddress {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}", self.context, self.kind.name())
+    }
+}
+
+impl std::str::FromStr for EventAddress {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.trim().split_once('/') {
+            Some((context_name, event_name)) => EventAddress::parse(context_name, event_name),
+            None => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad event name format: {string}"))),
+        }
+    }
+}
+
+// Serialized as "<context id>/<event name>", e.g. in the history queries
+impl Serialize for EventAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for EventAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl ContextEvent {
+    pub fn address(&self) -> EventAddress {
+        EventAddress::new(self.context.clone(), self.event.kind())
+    }
+}
+
+// Event of the default context instance
+impl From<Event> for ContextEvent {
+    fn from(event: Event) -> Self {
+        ContextEvent {
+            context: event.kind().context().into(),
+            stamp:   None,
+            event,
+        }
+    }
+}
+
+impl std::fmt::Display for BatteryStatus {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatteryStatus::Charging    => write!(formatter, "Charging"),
+            BatteryStatus::Discharging => write!(formatter, "Discharging"),
+            BatteryStatus::Full        => write!(formatter, "Full"),
+            BatteryStatus::NotCharging => write!(formatter, "NotCharging"),
+            BatteryStatus::Unknown     => write!(formatter, "Unknown"),
+        }
+    }
+}
+
+impl std::str::FromStr for BatteryStatus {
+    type Err = std::io::Error;
+
+    // NOTE "Not charging" is the value used by the kernel power supply class
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "Charging"                     => Ok(BatteryStatus::Charging),
+            "Discharging"                  => Ok(BatteryStatus::Discharging),
+            "Full"                         => Ok(BatteryStatus::Full),
+            "NotCharging" | "Not charging" => Ok(BatteryStatus::NotCharging),
+            "Unknown"                      => Ok(BatteryStatus::Unknown),
+            _ => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad status value: {string}"))),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use crate::event::EventAddress;
+
+// Arguments of the "rsbar/history" call: json object in both text and json representations.
+// Times are daemon timestamps in milliseconds since the unix epoch, "last" selects the samples of the last
+// milliseconds instead of "from". Samples are averaged over "step" milliseconds long intervals if it's set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct HistoryQuery {
+    pub event: EventAddress,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from:  Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to:    Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last:  Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step:  Option<u64>,
+}
+
+// Recorded value of an integer event. Averaged samples have the start time of their interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistorySample {
+    pub timestamp: u64,
+    pub value:     i64,
+}
+
+impl HistoryQuery {
+    // Whole recorded history of the event
+    pub fn new(event: EventAddress) -> Self {
+        HistoryQuery { event, from: None, to: None, last: None, step: None }
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{event::EventKind, value::WireValue};
+
+// Events and procedures of a context instance. Descriptions of the running contexts are returned
+// by the "rsbar/describe" call: { "<context name>": <context description> }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ContextDescription {
+    pub doc:        String,
+    pub events:     Vec<EventDescription>,
+    pub procedures: Vec<ProcedureDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventDescription {
+    pub name:  String,
+    pub value: ValueDescription,
+    pub doc:   String,
+}
+
+// Procedures without arguments or result have no "args" or "result" description
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcedureDescription {
+    pub name:   String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args:   Option<ValueDescription>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<ValueDescription>,
+    pub doc:    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueDescription {
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    // Inclusive range of an integer value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range:      Option<ValueRange>,
+    // Allowed values of an enum
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants:   Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ValueType {
+    Null,
+    Boolean,
+    Integer,
+    String,
+    Enum,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueRange {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl ContextDescription {
+    pub fn new(doc: &str, events: Vec<EventDescription>, procedures: Vec<ProcedureDescription>) -> Self {
+        ContextDescription { doc: doc.to_string(), events, procedures }
+    }
+}
+
+impl EventDescription {
+    // Value type is taken from the event declaration
+    pub fn new(kind: EventKind, doc: &str) -> Self {
+        EventDescription {
+            name:  kind.name().to_string(),
+            value: kind.value_description(),
+            doc:   doc.to_string(),
+        }
+    }
+}
+
+impl ProcedureDescription {
+    pub fn new(name: &str, args: Option<ValueDescription>, doc: &str) -> Self {
+        ProcedureDescription {
+            name:   name.to_string(),
+            result: None,
+            doc:    doc.to_string(),
+            args,
+        }
+    }
+}
+
+impl ValueDescription {
+    pub fn new(value_type: ValueType) -> Self {
+        ValueDescription { value_type, range: None, variants: Vec::new() }
+    }
+
+    pub fn of<T: WireValue>() -> Self {
+        ValueDescription::new(T::VALUE_TYPE)
+    }
+
+    pub fn integer(min: i64, max: i64) -> Self {
+        ValueDescription { range: Some(ValueRange { min, max }), ..ValueDescription::new(ValueType::Integer) }
+    }
+
+    pub fn enumeration<T: std::fmt::Display>(variants: &[T]) -> Self {
+        ValueDescription { variants: variants.iter().map(|variant| variant.to_string()).collect(), ..ValueDescription::new(ValueType::Enum) }
+    }
+}
+
+impl std::fmt::Display for ValueDescription {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.range, self.variants.is_empty()) {
+            (Some(range), _) => write!(formatter, "{} {}..={}", self.value_type, range.min, range.max),
+            (None, false)    => write!(formatter, "{} {}", self.value_type, self.variants.join("|")),
+            (None, true)     => write!(formatter, "{}", self.value_type),
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Null    => write!(formatter, "null"),
+            ValueType::Boolean => write!(formatter, "boolean"),
+            ValueType::Integer => write!(formatter, "integer"),
+            ValueType::String  => write!(formatter, "string"),
+            ValueType::Enum    => write!(formatter, "enum"),
+            ValueType::Object  => write!(formatter, "object"),
+        }
+    }
+}
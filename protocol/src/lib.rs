@@ -0,0 +1,21 @@
+mod call;
+mod context;
+mod event;
+mod history;
+mod introspection;
+mod paths;
+mod pattern;
+mod status;
+mod value;
+mod wire;
+
+pub use call::{Call, CallReply, ContextCall, ErrorCode};
+pub use context::{ContextId, ContextKind};
+pub use event::{BatteryStatus, ContextEvent, Event, EventAddress, EventKind, EventStamp, SubscriptionRequest};
+pub use history::{HistoryQuery, HistorySample};
+pub use introspection::{ContextDescription, EventDescription, ProcedureDescription, ValueDescription, ValueRange, ValueType};
+pub use paths::{take_flag, SocketPaths};
+pub use pattern::EventPattern;
+pub use status::{Backpressure, ContextState, ContextStatus, ReloadReport, SubscriberStatus, SubscribersStatus};
+pub use value::{value_to_text, WireValue};
+pub use wire::{ProtocolVersion, FRAME_DELIMITER, SUPPORTED_VERSIONS};
@@ -0,0 +1,95 @@
+use std::{io::ErrorKind, path::{Path, PathBuf}};
+
+// Sockets of a daemon instance are placed in "$XDG_RUNTIME_DIR/rsbar/<instance>".
+// The directory can be overridden with the --socket-dir flag or RSBAR_SOCKET_DIR variable,
+// instance name can be changed with the --instance flag or RSBAR_INSTANCE variable.
+// Flags take precedence over the environment
+const SOCKET_DIR_FLAG: &str = "--socket-dir";
+const INSTANCE_FLAG:   &str = "--instance";
+
+const SOCKET_DIR_VARIABLE: &str = "RSBAR_SOCKET_DIR";
+const INSTANCE_VARIABLE:   &str = "RSBAR_INSTANCE";
+
+const DEFAULT_INSTANCE: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketPaths {
+    dir: PathBuf,
+}
+
+impl SocketPaths {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        SocketPaths { dir: dir.into() }
+    }
+
+    // Removes the consumed flags from the arguments, so the rest could be passed further (e.g. to gtk)
+    pub fn from_args(args: &mut Vec<String>) -> std::io::Result<Self> {
+        let socket_dir = take_flag(args, SOCKET_DIR_FLAG)?.or_else(|| std::env::var(SOCKET_DIR_VARIABLE).ok());
+        let instance   = take_flag(args, INSTANCE_FLAG)?.or_else(|| std::env::var(INSTANCE_VARIABLE).ok());
+
+        if let Some(socket_dir) = socket_dir {
+            return Ok(SocketPaths::new(socket_dir));
+        }
+
+        let instance = instance.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+
+        if instance.is_empty() || instance.contains('/') || instance == "." || instance == ".." {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Bad instance name: {instance}")));
+        }
+
+        Ok(SocketPaths::new(base_dir().join(instance)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn call_socket(&self) -> PathBuf {
+        self.dir.join("call.sock")
+    }
+
+    pub fn event_socket(&self) -> PathBuf {
+        self.dir.join("event.sock")
+    }
+
+    // Held by the running daemon
+    pub fn lock_file(&self) -> PathBuf {
+        self.dir.join("daemon.lock")
+    }
+}
+
+// NOTE XDG_RUNTIME_DIR may be unset outside of a user session, so a per-user temporary dir is used instead.
+// Its name is predictable, so the daemon refuses to use it unless it's owned by the user and private
+fn base_dir() -> PathBuf {
+    if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        return PathBuf::from(runtime_dir).join("rsbar");
+    }
+
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+
+    std::env::temp_dir().join(format!("rsbar-{user}"))
+}
+
+// Removes the flag from the arguments and returns its value. Supports both "--flag value" and "--flag=value" forms
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> std::io::Result<Option<String>> {
+    let mut value = None;
+    let mut index = 0;
+
+    while index < args.len() {
+        if let Some(inline_value) = args[index].strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')) {
+            value = Some(inline_value.to_string());
+            args.remove(index);
+        } else if args[index] == flag {
+            if index + 1 >= args.len() {
+                return Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Missing value of the {flag} flag")));
+            }
+
+            value = Some(args.remove(index + 1));
+            args.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+
+    Ok(value)
+}
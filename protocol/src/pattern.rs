@@ -0,0 +1,154 @@
+use std::io::ErrorKind;
+
+use crate::event::{EventAddress, EventKind};
+
+// Subscription pattern: "<context pattern>/<event pattern>"
+// Each part is either a name, "*" (any name) or a list of names: "{name1,name2}".
+// For example: "volume/*", "*/*" or "battery/{capacity,status}".
+// Context names are matched exactly, so "battery/*" matches events of the default instance only,
+// "battery@BAT1/*" of the BAT1 instance and "*/*" of all of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventPattern {
+    context: NamePattern,
+    event:   NamePattern,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NamePattern {
+    Any,
+    Names(Vec<String>),
+}
+
+impl EventPattern {
+    pub fn matches(&self, address: &EventAddress) -> bool {
+        self.context.matches(&address.context.to_string()) && self.event.matches(address.kind.name())
+    }
+}
+
+impl NamePattern {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Any          => true,
+            NamePattern::Names(names) => names.iter().any(|pattern_name| pattern_name == name),
+        }
+    }
+}
+
+impl From<EventKind> for EventPattern {
+    fn from(kind: EventKind) -> Self {
+        EventPattern {
+            context: NamePattern::Names(vec![kind.context().name().to_string()]),
+            event:   NamePattern::Names(vec![kind.name().to_string()]),
+        }
+    }
+}
+
+impl From<EventAddress> for EventPattern {
+    fn from(address: EventAddress) -> Self {
+        EventPattern {
+            context: NamePattern::Names(vec![address.context.to_string()]),
+            event:   NamePattern::Names(vec![address.kind.name().to_string()]),
+        }
+    }
+}
+
+impl std::fmt::Display for EventPattern {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}/{}", self.context, self.event)
+    }
+}
+
+impl std::fmt::Display for NamePattern {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NamePattern::Any                              => write!(formatter, "*"),
+            NamePattern::Names(names) if names.len() == 1 => write!(formatter, "{}", names[0]),
+            NamePattern::Names(names)                     => write!(formatter, "{{{}}}", names.join(",")),
+        }
+    }
+}
+
+impl std::str::FromStr for EventPattern {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (context, event) = match string.trim().split_once('/') {
+            Some(split) => split,
+            None => return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad event pattern format: {string}"))),
+        };
+
+        Ok(EventPattern {
+            context: context.parse()?,
+            event:   event.parse()?,
+        })
+    }
+}
+
+impl std::str::FromStr for NamePattern {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string == "*" {
+            return Ok(NamePattern::Any);
+        }
+
+        let names: Vec<String> = match string.strip_prefix('{').and_then(|names| names.strip_suffix('}')) {
+            Some(names) => names.split(',').map(|name| name.trim().to_string()).collect(),
+            None        => vec![string.to_string()],
+        };
+
+        let is_valid_name = |name: &String| !name.is_empty() && !name.contains(['*', '{', '}', ',', '/']);
+
+        if !names.iter().all(is_valid_name) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad name pattern: {string}")));
+        }
+
+        Ok(NamePattern::Names(names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, address: &str) -> bool {
+        pattern.parse::<EventPattern>().unwrap().matches(&address.parse().unwrap())
+    }
+
+    #[test]
+    fn any_pattern_matches_every_instance() {
+        assert!(matches("*/*", "volume/volume"));
+        assert!(matches("*/*", "battery/capacity"));
+        assert!(matches("*/*", "battery@BAT1/status"));
+    }
+
+    #[test]
+    fn name_list_matches_the_listed_events_only() {
+        assert!(matches("battery/{capacity,status}", "battery/capacity"));
+        assert!(matches("battery/{capacity, status}", "battery/status"));
+        assert!(!matches("battery/{capacity,status}", "volume/volume"));
+        assert!(!matches("battery/{capacity}", "battery/status"));
+    }
+
+    #[test]
+    fn context_name_is_matched_exactly() {
+        assert!(matches("battery/*", "battery/capacity"));
+        assert!(!matches("battery/*", "battery@BAT1/capacity"));
+        assert!(matches("battery@BAT1/*", "battery@BAT1/capacity"));
+        assert!(!matches("battery@BAT1/*", "battery/capacity"));
+    }
+
+    #[test]
+    fn bad_patterns_are_rejected() {
+        for pattern in ["{}/*", "*/{}", "{}", "a/b/c", "{a,*}/*", "*/{a,*}", "volume", "/volume", "*/{a,,b}"] {
+            assert_eq!(pattern.parse::<EventPattern>().unwrap_err().kind(), ErrorKind::InvalidData, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn pattern_is_displayed_as_parsed() {
+        for pattern in ["*/*", "volume/volume", "battery@BAT1/{capacity,status}"] {
+            assert_eq!(pattern.parse::<EventPattern>().unwrap().to_string(), pattern);
+        }
+    }
+}
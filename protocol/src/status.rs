@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, io::ErrorKind};
+
+use serde::{Deserialize, Serialize};
+
+// State of each context running in the daemon: { "<context name>": <context state> }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextStatus(pub BTreeMap<String, ContextState>);
+
+// Degraded context is being restarted by the daemon, its widgets should be shown as inactive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum ContextState {
+    Starting,
+    Running,
+    Degraded { error: String },
+}
+
+// Result of a config reload: names of the affected contexts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReloadReport {
+    pub started:      Vec<String>,
+    pub stopped:      Vec<String>,
+    pub reconfigured: Vec<String>,
+    pub unchanged:    Vec<String>,
+    // Config couldn't be loaded, the running contexts were left as is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error:        Option<String>,
+}
+
+// Subscribers connected to the event socket: { "<connection id>": <subscriber status> }
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscribersStatus(pub BTreeMap<String, SubscriberStatus>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriberStatus {
+    pub peer:         String,
+    pub backpressure: Backpressure,
+    // Events which were dropped or coalesced, because the subscriber didn't keep up with them
+    pub dropped:      u64,
+}
+
+// Policy of the outgoing event queue of a subscriber, which doesn't read the events as fast as they are triggered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Backpressure {
+    // Only the latest value of each event is queued
+    #[default]
+    Coalesce,
+    // The oldest events are dropped once the queue is full
+    DropOldest,
+    // Subscriber is disconnected once its queue is full or an event isn't written within the timeout
+    Disconnect,
+}
+
+impl ContextStatus {
+    pub fn is_running(&self, context_name: &str) -> bool {
+        self.0.get(context_name) == Some(&ContextState::Running)
+    }
+}
+
+impl Backpressure {
+    pub const ALL: &'static [Backpressure] = &[Backpressure::Coalesce, Backpressure::DropOldest, Backpressure::Disconnect];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backpressure::Coalesce   => "coalesce",
+            Backpressure::DropOldest => "dropOldest",
+            Backpressure::Disconnect => "disconnect",
+        }
+    }
+}
+
+impl std::fmt::Display for Backpressure {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(formatter, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for Backpressure {
+    type Err = std::io::Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match Backpressure::ALL.iter().find(|policy| policy.name() == string.trim()) {
+            Some(policy) => Ok(*policy),
+            None => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Unknown backpressure policy: {string}"))),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::io::ErrorKind;
+
+use serde_json::Value;
+
+use crate::{event::BatteryStatus, history::HistoryQuery, introspection::ValueType, pattern::EventPattern, status::{ContextStatus, ReloadReport, SubscribersStatus}};
+
+// Conversion of event values and call arguments to the wire representation.
+// v1 protocol uses the text representation, v2 uses json
+pub trait WireValue: Sized {
+    // Described by the introspection (see ContextDescription)
+    const VALUE_TYPE: ValueType;
+
+    fn to_text(&self) -> String;
+    fn from_text(text: &str) -> std::io::Result<Self>;
+
+    fn to_json(&self) -> Value;
+    fn from_json(value: Value) -> std::io::Result<Self>;
+}
+
+macro_rules! wire_value {
+    ($($value_type:ty => $wire_type:ident,)*) => {
+        $(
+            impl WireValue for $value_type {
+                const VALUE_TYPE: ValueType = ValueType::$wire_type;
+
+                fn to_text(&self) -> String {
+                    self.to_string()
+                }
+
+                fn from_text(text: &str) -> std::io::Result<Self> {
+                    text.trim().parse::<$value_type>()
+                        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, format!("Bad {} value: {text}", stringify!($value_type))))
+                }
+
+                fn to_json(&self) -> Value {
+                    serde_json::to_value(self).unwrap_or_default()
+                }
+
+                // Strings are accepted too, so that v2 peers may send the text representation
+                fn from_json(value: Value) -> std::io::Result<Self> {
+                    match value {
+                        Value::String(text) => Self::from_text(&text),
+                        value => serde_json::from_value(value).map_err(|error| std::io::Error::new(ErrorKind::InvalidInput, error)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+wire_value! {
+    u32  => Integer,
+    i32  => Integer,
+    bool => Boolean,
+}
+
+// NOTE v1 peers get the kernel spelling ("Not charging") as before the v2 protocol, v2 peers get the variant names
+impl WireValue for BatteryStatus {
+    const VALUE_TYPE: ValueType = ValueType::Enum;
+
+    fn to_text(&self) -> String {
+        match self {
+            BatteryStatus::NotCharging => "Not charging".to_string(),
+            status                     => status.to_string(),
+        }
+    }
+
+    fn from_text(text: &str) -> std::io::Result<Self> {
+        text.trim().parse::<BatteryStatus>()
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, format!("Bad BatteryStatus value: {text}")))
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn from_json(value: Value) -> std::io::Result<Self> {
+        match value {
+            Value::String(text) => Self::from_text(&text),
+            value => serde_json::from_value(value).map_err(|error| std::io::Error::new(ErrorKind::InvalidInput, error)),
+        }
+    }
+}
+
+// Structured values: json is used for both text and json representations
+macro_rules! json_value {
+    ($($value_type:ty)*) => {
+        $(
+            impl WireValue for $value_type {
+                const VALUE_TYPE: ValueType = ValueType::Object;
+
+                fn to_text(&self) -> String {
+                    serde_json::to_string(self).unwrap_or_default()
+                }
+
+                fn from_text(text: &str) -> std::io::Result<Self> {
+                    serde_json::from_str(text).map_err(|error| std::io::Error::new(ErrorKind::InvalidInput, error))
+                }
+
+                fn to_json(&self) -> Value {
+                    serde_json::to_value(self).unwrap_or_default()
+                }
+
+                fn from_json(value: Value) -> std::io::Result<Self> {
+                    serde_json::from_value(value).map_err(|error| std::io::Error::new(ErrorKind::InvalidInput, error))
+                }
+            }
+        )*
+    };
+}
+
+json_value!(ContextStatus ReloadReport SubscribersStatus HistoryQuery);
+
+impl WireValue for String {
+    const VALUE_TYPE: ValueType = ValueType::String;
+
+    fn to_text(&self) -> String {
+        self.clone()
+    }
+
+    fn from_text(text: &str) -> std::io::Result<Self> {
+        Ok(text.to_string())
+    }
+
+    fn to_json(&self) -> Value {
+        Value::String(self.clone())
+    }
+
+    fn from_json(value: Value) -> std::io::Result<Self> {
+        match value {
+            Value::String(text) => Ok(text),
+            value => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Expected string, got {value}"))),
+        }
+    }
+}
+
+// Event patterns of a query: separated by spaces in the text representation, json array of strings in json.
+// A single string is accepted in json too
+impl WireValue for Vec<EventPattern> {
+    const VALUE_TYPE: ValueType = ValueType::String;
+
+    fn to_text(&self) -> String {
+        self.iter().map(|pattern| pattern.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    fn from_text(text: &str) -> std::io::Result<Self> {
+        parse_patterns(text.split_whitespace().map(str::to_string))
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(|pattern| Value::String(pattern.to_string())).collect())
+    }
+
+    fn from_json(value: Value) -> std::io::Result<Self> {
+        match value {
+            Value::String(text)  => Self::from_text(&text),
+            Value::Array(values) => parse_patterns(values.into_iter().map(|value| match value {
+                Value::String(text) => text,
+                value               => value.to_string(),
+            })),
+            value => Err(std::io::Error::new(ErrorKind::InvalidInput, format!("Expected event patterns, got {value}"))),
+        }
+    }
+}
+
+fn parse_patterns(patterns: impl Iterator<Item = String>) -> std::io::Result<Vec<EventPattern>> {
+    let patterns = patterns
+        .map(|pattern| pattern.parse::<EventPattern>().map_err(|error| std::io::Error::new(ErrorKind::InvalidInput, error)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    if patterns.is_empty() {
+        return Err(std::io::Error::new(ErrorKind::InvalidInput, "Missing event pattern"));
+    }
+
+    Ok(patterns)
+}
+
+// Events without a value, e.g. "rsbar/shutdown/"
+impl WireValue for () {
+    const VALUE_TYPE: ValueType = ValueType::Null;
+
+    fn to_text(&self) -> String {
+        String::new()
+    }
+
+    fn from_text(_text: &str) -> std::io::Result<Self> {
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Null
+    }
+
+    fn from_json(_value: Value) -> std::io::Result<Self> {
+        Ok(())
+    }
+}
+
+// Text representation of an untyped value (e.g. call result): strings are sent as is, everything else as json
+pub fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Null           => String::new(),
+        Value::String(string) => string.clone(),
+        value                 => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ContextEvent, Event, ProtocolVersion};
+
+    use super::*;
+
+    #[test]
+    fn battery_status_keeps_the_kernel_spelling_on_v1() {
+        let event: ContextEvent = Event::BatteryStatus(BatteryStatus::NotCharging).into();
+
+        assert_eq!(ProtocolVersion::V1Text.encode_event(&event), "battery/status/Not charging");
+        assert!(ProtocolVersion::V2Json.encode_event(&event).contains(r#""value":"NotCharging""#));
+
+        for version in [ProtocolVersion::V1Text, ProtocolVersion::V2Json] {
+            assert_eq!(version.decode_event(&version.encode_event(&event)).unwrap(), event);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{call::{Call, CallArgs, CallReply, ContextCall, ErrorCode}, event::{ContextEvent, EventAddress, EventStamp, SubscriptionRequest}, pattern::EventPattern, status::Backpressure, value::value_to_text};
+
+// Protocol negotiation:
+// The first frame sent by a client may be a hello request: "rsbar/hello/<comma separated versions>".
+// The server answers with "rsbar/hello/<chosen version>" and both sides switch to that version.
+// Clients that do not send a hello are served with the legacy v1 protocol. Old servers don't answer
+// the hello request, so clients should fall back to v1 after a timeout.
+//
+// v1 (text): "<event pattern>" subscriptions, "unsubscribe/<event pattern>" unsubscriptions (see EventPattern),
+//            "snapshot/<event pattern>" requests of the last values,
+//            "backpressure/<policy>" queue policy changes (see Backpressure),
+//            "<context id>/<event name>/<value>" events (without stamps),
+//            "<context id>/<procedure name>/<args>" calls, "ok/<value>" or "error/<code>/<message>" replies
+// v2 (json): each frame is a single json message (see ClientMessage and ServerMessage)
+//
+// Frames of both versions are terminated by '\0' (json escapes it inside strings)
+
+const HELLO_PREFIX: &str = "rsbar/hello/";
+const UNSUBSCRIBE_PREFIX: &str = "unsubscribe/";
+const SNAPSHOT_PREFIX: &str = "snapshot/";
+const BACKPRESSURE_PREFIX: &str = "backpressure/";
+
+pub const FRAME_DELIMITER: u8 = b'\0';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtocolVersion {
+    V1Text = 1,
+    V2Json = 2,
+}
+
+pub const SUPPORTED_VERSIONS: [ProtocolVersion; 2] = [ProtocolVersion::V1Text, ProtocolVersion::V2Json];
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    Subscribe { event: String },
+    Unsubscribe { event: String },
+    Snapshot { event: String },
+    Backpressure { policy: String },
+    Call { context: String, procedure: String, #[serde(default)] args: Value },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    Event {
+        context:   String,
+        event:     String,
+        value:     Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq:       Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        coalesced: Option<u64>,
+    },
+    Ok { value: Option<Value> },
+    Error { code: String, message: String },
+}
+
+impl ProtocolVersion {
+    pub fn hello_request() -> String {
+        let versions: Vec<String> = SUPPORTED_VERSIONS.iter().map(|version| (*version as u32).to_string()).collect();
+
+        format!("{HELLO_PREFIX}{}", versions.join(","))
+    }
+
+    // Returns the highest supported version if frame is a hello request (or hello reply)
+    pub fn from_hello(frame: &str) -> Option<ProtocolVersion> {
+        let versions = frame.trim().strip_prefix(HELLO_PREFIX)?;
+
+        let negotiated = versions.split(',')
+            .filter_map(|version| version.trim().parse::<u32>().ok())
+            .filter_map(|version| SUPPORTED_VERSIONS.into_iter().find(|supported| *supported as u32 == version))
+            .max();
+
+        Some(negotiated.unwrap_or(ProtocolVersion::V1Text))
+    }
+
+    pub fn hello_reply(&self) -> String {
+        format!("{HELLO_PREFIX}{}", *self as u32)
+    }
+
+    pub fn encode_subscription(&self, pattern: impl Into<EventPattern>) -> String {
+        let pattern = pattern.into();
+
+        match self {
+            ProtocolVersion::V1Text => pattern.to_string(),
+            ProtocolVersion::V2Json => to_json(&ClientMessage::Subscribe { event: pattern.to_string() }),
+        }
+    }
+
+    pub fn encode_unsubscription(&self, pattern: impl Into<EventPattern>) -> String {
+        let pattern = pattern.into();
+
+        match self {
+            ProtocolVersion::V1Text => format!("{UNSUBSCRIBE_PREFIX}{pattern}"),
+            ProtocolVersion::V2Json => to_json(&ClientMessage::Unsubscribe { event: pattern.to_string() }),
+        }
+    }
+
+    pub fn encode_snapshot(&self, pattern: impl Into<EventPattern>) -> String {
+        let pattern = pattern.into();
+
+        match self {
+            ProtocolVersion::V1Text => format!("{SNAPSHOT_PREFIX}{pattern}"),
+            ProtocolVersion::V2Json => to_json(&ClientMessage::Snapshot { event: pattern.to_string() }),
+        }
+    }
+
+    pub fn encode_backpressure(&self, policy: Backpressure) -> String {
+        match self {
+            ProtocolVersion::V1Text => format!("{BACKPRESSURE_PREFIX}{policy}"),
+            ProtocolVersion::V2Json => to_json(&ClientMessage::Backpressure { policy: policy.to_string() }),
+        }
+    }
+
+    pub fn decode_subscription(&self, frame: &str) -> std::io::Result<SubscriptionRequest> {
+        match self {
+            ProtocolVersion::V1Text => {
+                let frame = frame.trim();
+
+                if let Some(event) = frame.strip_prefix(UNSUBSCRIBE_PREFIX) {
+                    return Ok(SubscriptionRequest::Unsubscribe(event.parse()?));
+                }
+
+                if let Some(event) = frame.strip_prefix(SNAPSHOT_PREFIX) {
+                    return Ok(SubscriptionRequest::Snapshot(event.parse()?));
+                }
+
+                if let Some(policy) = frame.strip_prefix(BACKPRESSURE_PREFIX) {
+                    return Ok(SubscriptionRequest::Backpressure(policy.parse()?));
+                }
+
+                Ok(SubscriptionRequest::Subscribe(frame.parse()?))
+            },
+            ProtocolVersion::V2Json => match serde_json::from_str::<ClientMessage>(frame)? {
+                ClientMessage::Subscribe { event }     => Ok(SubscriptionRequest::Subscribe(event.parse()?)),
+                ClientMessage::Unsubscribe { event }   => Ok(SubscriptionRequest::Unsubscribe(event.parse()?)),
+                ClientMessage::Snapshot { event }      => Ok(SubscriptionRequest::Snapshot(event.parse()?)),
+                ClientMessage::Backpressure { policy } => Ok(SubscriptionRequest::Backpressure(policy.parse()?)),
+                _ => Err(std::io::Error::new(ErrorKind::InvalidData, "Only subscription requests are accepted on the event socket")),
+            },
+        }
+    }
+
+    // Call is addressed to the default context instance unless ContextCall is passed
+    pub fn encode_call(&self, call: impl Into<ContextCall>) -> String {
+        let ContextCall { context, call } = call.into();
+
+        match self {
+            ProtocolVersion::V1Text => format!("{context}/{}/{}", call.procedure(), call.args_to_text()),
+            ProtocolVersion::V2Json => to_json(&ClientMessage::Call {
+                context:   context.to_string(),
+                procedure: call.procedure().to_string(),
+                args:      call.args_to_json(),
+            }),
+        }
+    }
+
+    // See Call::parse for the error kinds
+    pub fn decode_call(&self, frame: &str) -> std::io::Result<ContextCall> {
+        match self {
+            ProtocolVersion::V1Text => {
+                let request_parts: Vec<&str> = frame.trim().splitn(3, '/').collect();
+
+                if request_parts.len() != 3 {
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Invalid request parts count: {frame}")));
+                }
+
+                Call::parse(request_parts[0], request_parts[1], CallArgs::Text(request_parts[2].to_string()))
+            },
+            ProtocolVersion::V2Json => match serde_json::from_str::<ClientMessage>(frame)? {
+                ClientMessage::Call { context, procedure, args } => Call::parse(&context, &procedure, CallArgs::Json(args)),
+                _ => Err(std::io::Error::new(ErrorKind::InvalidData, "Only calls are accepted on the call socket")),
+            },
+        }
+    }
+
+    pub fn encode_event(&self, event: &ContextEvent) -> String {
+        match self {
+            ProtocolVersion::V1Text => format!("{}/{}", event.address(), event.event.value_to_text()),
+            ProtocolVersion::V2Json => to_json(&ServerMessage::Event {
+                context:   event.context.to_string(),
+                event:     event.event.kind().name().to_string(),
+                value:     event.event.value_to_json(),
+                seq:       event.stamp.map(|stamp| stamp.seq),
+                timestamp: event.stamp.map(|stamp| stamp.timestamp),
+                coalesced: event.stamp.map(|stamp| stamp.coalesced).filter(|coalesced| *coalesced > 0),
+            }),
+        }
+    }
+
+    pub fn decode_event(&self, frame: &str) -> std::io::Result<ContextEvent> {
+        let (address, event, stamp) = match self {
+            ProtocolVersion::V1Text => match split_at_nth_char_ex(frame, '/', 1) {
+                Some((name, value)) => {
+                    let address = name.parse::<EventAddress>()?;
+                    let event   = address.kind.value_from_text(value)?;
+
+                    (address, event, None)
+                },
+                None => return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad event format: {frame}"))),
+            },
+            ProtocolVersion::V2Json => match serde_json::from_str::<ServerMessage>(frame)? {
+                ServerMessage::Event { context, event, value, seq, timestamp, coalesced } => {
+                    let address = EventAddress::parse(&context, &event)?;
+                    let event   = address.kind.value_from_json(value)?;
+
+                    // NOTE events of the older servers aren't stamped
+                    let stamp = seq.zip(timestamp).map(|(seq, timestamp)| EventStamp { seq, timestamp, coalesced: coalesced.unwrap_or(0) });
+
+                    (address, event, stamp)
+                },
+                _ => return Err(std::io::Error::new(ErrorKind::InvalidData, format!("Unexpected message on the event socket: {frame}"))),
+            },
+        };
+
+        Ok(ContextEvent { context: address.context, event, stamp })
+    }
+
+    pub fn encode_reply(&self, reply: &CallReply) -> String {
+        match (self, reply) {
+            (ProtocolVersion::V1Text, CallReply::Ok(value)) => format!("ok/{}", value.as_ref().map(value_to_text).unwrap_or_default()),
+            (ProtocolVersion::V1Text, CallReply::Error(code, message)) => format!("error/{code}/{message}"),
+            (ProtocolVersion::V2Json, CallReply::Ok(value)) => to_json(&ServerMessage::Ok { value: value.clone() }),
+            (ProtocolVersion::V2Json, CallReply::Error(code, message)) => to_json(&ServerMessage::Error { code: code.to_string(), message: message.clone() }),
+        }
+    }
+
+    // NOTE unknown error codes are decoded as ErrorCode::Failed
+    pub fn decode_reply(&self, frame: &str) -> std::io::Result<CallReply> {
+        match self {
+            ProtocolVersion::V1Text => {
+                if let Some(value) = frame.strip_prefix("ok/") {
+                    return Ok(CallReply::Ok((!value.is_empty()).then(|| Value::String(value.to_string()))));
+                }
+
+                if let Some((code, message)) = frame.strip_prefix("error/").and_then(|error| error.split_once('/')) {
+                    return Ok(CallReply::Error(code.parse().unwrap_or(ErrorCode::Failed), message.to_string()));
+                }
+
+                Err(std::io::Error::new(ErrorKind::InvalidData, format!("Bad call reply format: {frame}")))
+            },
+            ProtocolVersion::V2Json => match serde_json::from_str::<ServerMessage>(frame)? {
+                ServerMessage::Ok { value }            => Ok(CallReply::Ok(value)),
+                ServerMessage::Error { code, message } => Ok(CallReply::Error(code.parse().unwrap_or(ErrorCode::Failed), message)),
+                _ => Err(std::io::Error::new(ErrorKind::InvalidData, format!("Unexpected message on the call socket: {frame}"))),
+            },
+        }
+    }
+}
+
+fn to_json<T: Serialize>(message: &T) -> String {
+    // NOTE serialization of these messages can't fail: all of the keys are strings
+    serde_json::to_string(message).unwrap_or_default()
+}
+
+fn split_at_nth_char(s: &str, p: char, n: usize) -> Option<(&str, &str)> {
+    s.match_indices(p).nth(n).map(|(index, _)| s.split_at(index))
+}
+
+fn split_at_nth_char_ex(s: &str, p: char, n: usize) -> Option<(&str, &str)> {
+    split_at_nth_char(s, p, n).map(|(left, right)| {
+        (
+            left,
+            // Trim 1 character.
+            &right[right.char_indices().nth(1).map_or(right.len(), |(index, _)| index)..],
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BatteryStatus, ContextKind, Event};
+
+    use super::*;
+
+    const VERSIONS: [ProtocolVersion; 2] = [ProtocolVersion::V1Text, ProtocolVersion::V2Json];
+
+    #[test]
+    fn events_are_round_tripped() {
+        let event = ContextEvent {
+            context: "battery@BAT1".parse().unwrap(),
+            event:   Event::BatteryStatus(BatteryStatus::Charging),
+            stamp:   None,
+        };
+
+        for version in VERSIONS {
+            assert_eq!(version.decode_event(&version.encode_event(&event)).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn stamps_are_round_tripped_on_v2() {
+        for coalesced in [0, 3] {
+            let event = ContextEvent {
+                context: ContextKind::Volume.into(),
+                event:   Event::Volume(40),
+                stamp:   Some(EventStamp { seq: 7, timestamp: 1_700_000_000_000, coalesced }),
+            };
+
+            assert_eq!(ProtocolVersion::V2Json.decode_event(&ProtocolVersion::V2Json.encode_event(&event)).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn stamps_are_dropped_on_v1() {
+        let event = ContextEvent {
+            context: ContextKind::Volume.into(),
+            event:   Event::Volume(40),
+            stamp:   Some(EventStamp { seq: 7, timestamp: 1_700_000_000_000, coalesced: 0 }),
+        };
+
+        assert_eq!(ProtocolVersion::V1Text.encode_event(&event), "volume/volume/40");
+        assert_eq!(ProtocolVersion::V1Text.decode_event("volume/volume/40").unwrap().stamp, None);
+    }
+
+    #[test]
+    fn v1_value_with_slashes_is_kept_whole() {
+        let event: ContextEvent = Event::Time("18/10/2026 12:00".to_string()).into();
+
+        assert_eq!(ProtocolVersion::V1Text.encode_event(&event), "time/time/18/10/2026 12:00");
+        assert_eq!(ProtocolVersion::V1Text.decode_event("time/time/18/10/2026 12:00").unwrap(), event);
+    }
+
+    #[test]
+    fn calls_are_round_tripped() {
+        let calls = [
+            ContextCall::from(Call::SetVolume(40)),
+            ContextCall::from(Call::ToggleMute),
+            ContextCall { context: "brightness@intel_backlight".parse().unwrap(), call: Call::SetBrightness(70) },
+        ];
+
+        for version in VERSIONS {
+            for call in &calls {
+                assert_eq!(version.decode_call(&version.encode_call(call.clone())).unwrap(), *call);
+            }
+        }
+    }
+
+    #[test]
+    fn replies_are_round_tripped() {
+        let replies = [
+            CallReply::Ok(None),
+            CallReply::Ok(Some(Value::String("ok/with/slashes".to_string()))),
+            CallReply::Error(ErrorCode::PermissionDenied, "Not allowed: volume/setVolume".to_string()),
+        ];
+
+        for version in VERSIONS {
+            for reply in &replies {
+                assert_eq!(version.decode_reply(&version.encode_reply(reply)).unwrap(), *reply);
+            }
+        }
+
+        let reply = CallReply::Ok(Some(serde_json::json!({ "volume": 40 })));
+
+        assert_eq!(ProtocolVersion::V2Json.decode_reply(&ProtocolVersion::V2Json.encode_reply(&reply)).unwrap(), reply);
+    }
+
+    #[test]
+    fn unknown_error_code_is_decoded_as_failed() {
+        assert_eq!(ProtocolVersion::V1Text.decode_reply("error/exploded/Boom").unwrap(), CallReply::Error(ErrorCode::Failed, "Boom".to_string()));
+        assert!(ProtocolVersion::V1Text.decode_reply("maybe/40").is_err());
+    }
+
+    #[test]
+    fn hello_negotiates_the_highest_common_version() {
+        assert_eq!(ProtocolVersion::from_hello(&ProtocolVersion::hello_request()), Some(ProtocolVersion::V2Json));
+        assert_eq!(ProtocolVersion::from_hello("rsbar/hello/1,2"), Some(ProtocolVersion::V2Json));
+        assert_eq!(ProtocolVersion::from_hello("rsbar/hello/1"), Some(ProtocolVersion::V1Text));
+        assert_eq!(ProtocolVersion::from_hello(&ProtocolVersion::V2Json.hello_reply()), Some(ProtocolVersion::V2Json));
+    }
+
+    #[test]
+    fn unknown_hello_version_falls_back_to_v1() {
+        assert_eq!(ProtocolVersion::from_hello("rsbar/hello/7"), Some(ProtocolVersion::V1Text));
+        assert_eq!(ProtocolVersion::from_hello("rsbar/hello/"), Some(ProtocolVersion::V1Text));
+        assert_eq!(ProtocolVersion::from_hello("volume/volume/40"), None);
+    }
+}